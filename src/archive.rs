@@ -0,0 +1,259 @@
+// Copyright 2024 Felipe Torres González
+
+//! Read data files that are stored inside compressed archives.
+//!
+//! Snapshots of a whole session are usually archived together once the session is over.
+//! This module allows feeding the content of those archives straight to the parser, with
+//! no need to extract them first.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use flate2::read::GzDecoder;
+
+/// Types of containers that are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    /// A zip file (`.zip`).
+    Zip,
+    /// An uncompressed tarball (`.tar`).
+    Tar,
+    /// A gzip compressed tarball (`.tar.gz` or `.tgz`).
+    TarGz,
+    /// A single gzip compressed file (`.gz`).
+    Gz,
+}
+
+impl ArchiveKind {
+    /// Identify the type of container of a file using its name.
+    ///
+    /// `None` is returned when the file is not a supported container.
+    pub fn from_path(path: &Path) -> Option<ArchiveKind> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".gz") {
+            Some(ArchiveKind::Gz)
+        } else {
+            None
+        }
+    }
+}
+
+/// A data file extracted from an archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    /// Name of the entry inside the archive (stem + extension, no inner directories).
+    pub name: String,
+    /// Raw content of the entry.
+    pub data: Vec<u8>,
+}
+
+/// Check whether a file name satisfies the filters used by [discover][crate::discover].
+pub(crate) fn matches(name: &str, filter: &str, format: &str) -> bool {
+    let path = Path::new(name);
+
+    let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("_");
+    let stem = path.file_stem().and_then(|x| x.to_str()).unwrap_or("");
+
+    extension == format && stem.starts_with(filter)
+}
+
+/// Extract the data files contained in an archive.
+///
+/// # Description
+///
+/// This function opens the archive pointed by `path` and reads the content of the entries
+/// whose names satisfy the given filters. Only the file name of each entry is considered,
+/// so data files stored inside folders of the archive are also found.
+///
+/// The entries are returned in the same order in which they are stored in the archive.
+///
+/// ## Arguments
+///
+/// - `path` points to an archive whose type is identified by [ArchiveKind::from_path].
+/// - `filter` and `format` have the same meaning as in [discover][crate::discover].
+///
+/// ## Return
+///
+/// A vector with the matching entries. An error is returned if the file is not a supported
+/// archive or its content can't be read.
+///
+/// # Example of use
+///
+/// ```rust,no_run
+/// use ibex_parser::archive::read_entries;
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use std::path::Path;
+///
+/// let parser = IbexParser::new();
+/// let entries = read_entries(Path::new("./2024-02-06.tar.gz"), None, None).unwrap();
+///
+/// for entry in entries {
+///     println!("{:?}", parser.parse_bytes(&entry.data));
+/// }
+/// ```
+pub fn read_entries(
+    path: &Path,
+    filter: Option<&str>,
+    format: Option<&str>
+) -> io::Result<Vec<ArchiveEntry>> {
+    let filter = filter.unwrap_or("data_ibex");
+    let format = format.unwrap_or("csv");
+
+    read_matching(path, |x| matches(x, filter, format))
+}
+
+/// Extract all the files contained in an archive, whatever their names.
+///
+/// This is the same as [read_entries] with no filters, and it is meant for data files that are
+/// identified by their content (see [sniff][crate::detect::sniff]).
+pub fn read_all(path: &Path) -> io::Result<Vec<ArchiveEntry>> {
+    read_matching(path, |_| true)
+}
+
+/// Extract the files of an archive whose names satisfy `keep`. The names are checked before
+/// the content of each entry is read, so the rest of the entries are not decompressed.
+fn read_matching(path: &Path, keep: impl Fn(&str) -> bool) -> io::Result<Vec<ArchiveEntry>> {
+    let kind = ArchiveKind::from_path(path).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Not a supported archive")
+    })?;
+    let file = BufReader::new(File::open(path)?);
+
+    Ok(match kind {
+        ArchiveKind::Zip => read_zip(file, keep)?,
+        ArchiveKind::Tar => read_tar(file, keep)?,
+        ArchiveKind::TarGz => read_tar(GzDecoder::new(file), keep)?,
+        ArchiveKind::Gz => {
+            // A gzip file only stores a single file, named after the archive minus `.gz`.
+            let name = path.file_stem().and_then(|x| x.to_str()).unwrap_or("").to_string();
+            if !keep(&name) {
+                return Ok(Vec::new());
+            }
+            let mut data = Vec::new();
+            GzDecoder::new(file).read_to_end(&mut data)?;
+            vec![ArchiveEntry { name, data }]
+        },
+    })
+}
+
+fn read_tar<R: Read>(reader: R, keep: impl Fn(&str) -> bool) -> io::Result<Vec<ArchiveEntry>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let name = match entry.path()?.file_name().and_then(|x| x.to_str()) {
+            Some(x) if keep(x) => x.to_string(),
+            _ => continue,
+        };
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        entries.push(ArchiveEntry { name, data });
+    }
+
+    Ok(entries)
+}
+
+fn read_zip<R: Read + io::Seek>(reader: R, keep: impl Fn(&str) -> bool) -> io::Result<Vec<ArchiveEntry>> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(io::Error::other)?;
+    let mut entries = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(io::Error::other)?;
+
+        if !entry.is_file() {
+            continue;
+        }
+
+        let name = match Path::new(entry.name()).file_name().and_then(|x| x.to_str()) {
+            Some(x) if keep(x) => x.to_string(),
+            _ => continue,
+        };
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        entries.push(ArchiveEntry { name, data });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+
+    #[rstest]
+    #[case("snapshots.zip", Some(ArchiveKind::Zip))]
+    #[case("snapshots.tar", Some(ArchiveKind::Tar))]
+    #[case("snapshots.tar.gz", Some(ArchiveKind::TarGz))]
+    #[case("snapshots.TGZ", Some(ArchiveKind::TarGz))]
+    #[case("data_ibex(2).csv.gz", Some(ArchiveKind::Gz))]
+    #[case("data_ibex.csv", None)]
+    fn test_archivekind_from_path(#[case] name: &str, #[case] expected: Option<ArchiveKind>) {
+        assert_eq!(ArchiveKind::from_path(Path::new(name)), expected);
+    }
+
+    // Each archive contains data_ibex.csv, data_ibex(1).csv and a README.txt.
+    #[rstest]
+    #[case("./tests/data/archives/snapshots.zip")]
+    #[case("./tests/data/archives/snapshots.tar")]
+    #[case("./tests/data/archives/snapshots.tar.gz")]
+    fn test_read_entries(#[case] path: &str) {
+        let entries = read_entries(Path::new(path), None, None).unwrap();
+        let names: Vec<&str> = entries.iter().map(|x| x.name.as_str()).collect();
+
+        assert_eq!(names, vec!["data_ibex.csv", "data_ibex(1).csv"]);
+        assert!(entries.iter().all(|x| !x.data.is_empty()));
+    }
+
+    #[rstest]
+    fn test_read_entries_gz() {
+        let path = Path::new("./tests/data/archives/data_ibex(2).csv.gz");
+        let entries = read_entries(path, None, None).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "data_ibex(2).csv");
+    }
+
+    #[rstest]
+    fn test_read_entries_gz_filter() {
+        let path = Path::new("./tests/data/archives/data_ibex(2).csv.gz");
+
+        assert_eq!(read_entries(path, Some("data_ibex_"), None).unwrap(), vec![]);
+    }
+
+    #[rstest]
+    fn test_read_entries_custom_filter() {
+        let path = Path::new("./tests/data/archives/snapshots.zip");
+        let entries = read_entries(path, Some("README"), Some("txt")).unwrap();
+
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[rstest]
+    fn test_read_all() {
+        let entries = read_all(Path::new("./tests/data/archives/snapshots.tar")).unwrap();
+        let names: Vec<&str> = entries.iter().map(|x| x.name.as_str()).collect();
+
+        assert_eq!(names, vec!["data_ibex.csv", "data_ibex(1).csv", "README.txt"]);
+    }
+
+    #[rstest]
+    fn test_read_entries_not_archive() {
+        let path = Path::new("./tests/data/data_ibex.csv");
+
+        assert!(read_entries(path, None, None).is_err());
+    }
+}
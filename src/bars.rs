@@ -0,0 +1,281 @@
+// Copyright 2024 Felipe Torres González
+
+//! Aggregation of the quotes of the stocks into bars.
+//!
+//! Each data file is a snapshot of the prices at some time of the session, so a directory
+//! of data files saved every few minutes is a sampled series of prices. This module groups
+//! those samples into bars of a fixed interval (OHLC), as most analysis tools expect.
+//!
+//! The volume and the turnover found in the data files are accumulated since the beginning
+//! of the session. The volume and the turnover of a bar are the difference between the
+//! accumulated values at the end of the bar and at the end of the previous bar.
+
+use std::collections::BTreeMap;
+use chrono::{NaiveDate, NaiveTime, TimeDelta, Timelike};
+use crate::calendar::Calendar;
+use crate::quote::StockQuote;
+
+pub use crate::calendar::CLOSE_TIME;
+
+/// A bar of the prices of a stock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bar {
+    /// Name of the stock, as shown in the data files.
+    pub ticker: String,
+    /// Date of the session.
+    pub date: NaiveDate,
+    /// Beginning of the interval covered by the bar.
+    pub start: NaiveTime,
+    /// First price of the interval.
+    pub open: f64,
+    /// Highest price of the interval.
+    pub high: f64,
+    /// Lowest price of the interval.
+    pub low: f64,
+    /// Last price of the interval.
+    pub close: f64,
+    /// Number of shares traded during the interval.
+    pub volume: u64,
+    /// Turnover of the interval, in euros.
+    pub turnover: f64,
+}
+
+/// Parse the length of an interval, e.g. `30s`, `5m` or `1h`.
+///
+/// A number with no unit is taken as minutes. `None` is returned for wrong or empty
+/// intervals.
+pub fn parse_interval(value: &str) -> Option<TimeDelta> {
+    let (number, unit) = match value.find(|x: char| !x.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "m"),
+    };
+    let number: i64 = number.parse().ok()?;
+
+    let interval = match unit {
+        "s" => TimeDelta::try_seconds(number)?,
+        "m" => TimeDelta::try_minutes(number)?,
+        "h" => TimeDelta::try_hours(number)?,
+        _ => return None,
+    };
+
+    (interval > TimeDelta::zero() && interval <= TimeDelta::days(1)).then_some(interval)
+}
+
+/// Beginning of the interval that contains a time.
+fn interval_start(time: NaiveTime, interval: TimeDelta) -> NaiveTime {
+    let length = interval.num_seconds() as u32;
+    let seconds = time.num_seconds_from_midnight() / length * length;
+
+    NaiveTime::from_num_seconds_from_midnight_opt(seconds, 0).unwrap()
+}
+
+/// Aggregate the quotes of the stocks into bars.
+///
+/// # Description
+///
+/// Quotes are grouped by stock and session, and sorted by their time, so they can be given
+/// in any order. Closing prices are placed at the end of the closing auction of their
+/// session (see [close_time][crate::calendar::close_time]). When several quotes share the
+/// same time, e.g. the same data file was saved twice, the last one is used.
+///
+/// Intervals with no quotes produce no bar.
+///
+/// ## Return
+///
+/// The bars sorted by stock, date and time.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::bars::{aggregate, parse_interval};
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use std::fs::read;
+///
+/// let quotes = IbexParser::new().parse_quotes(&read("./tests/data/data_ibex.csv").unwrap()).unwrap();
+/// let bars = aggregate(&quotes.stocks, parse_interval("5m").unwrap());
+///
+/// assert_eq!(bars.len(), 35);
+/// ```
+pub fn aggregate(quotes: &[StockQuote], interval: TimeDelta) -> Vec<Bar> {
+    let mut sessions: BTreeMap<(&str, NaiveDate), BTreeMap<NaiveTime, &StockQuote>> = BTreeMap::new();
+
+    for quote in quotes {
+        sessions
+            .entry((&quote.ticker, quote.date))
+            .or_default()
+            .insert(quote.published_at().time(), quote);
+    }
+
+    let mut bars: Vec<Bar> = Vec::new();
+
+    for ((ticker, date), session) in sessions {
+        // Accumulated values at the end of the previous bar.
+        let (mut volume, mut turnover) = (0, 0.0);
+
+        for (time, quote) in session {
+            let start = interval_start(time, interval);
+
+            match bars.last_mut() {
+                Some(bar) if bar.ticker == ticker && bar.date == date && bar.start == start => {
+                    bar.high = bar.high.max(quote.last);
+                    bar.low = bar.low.min(quote.last);
+                    bar.close = quote.last;
+                    bar.volume = quote.volume.saturating_sub(volume);
+                    bar.turnover = quote.turnover - turnover;
+                },
+                last => {
+                    if let Some(bar) = last.filter(|x| x.ticker == ticker && x.date == date) {
+                        volume += bar.volume;
+                        turnover += bar.turnover;
+                    }
+
+                    bars.push(Bar {
+                        ticker: String::from(ticker),
+                        date,
+                        start,
+                        open: quote.last,
+                        high: quote.last,
+                        low: quote.last,
+                        close: quote.last,
+                        volume: quote.volume.saturating_sub(volume),
+                        turnover: quote.turnover - turnover,
+                    });
+                },
+            }
+        }
+    }
+
+    bars
+}
+
+/// Aggregate the quotes of the stocks into bars, skipping the quotes published out of a
+/// session of the market: on non-trading days, or out of the hours of the session (see
+/// [Calendar::phase]). Closing prices are kept for every trading day, at the end of its
+/// closing auction, including the shorter sessions of the 24th and 31st of December.
+///
+/// The volume and the turnover of the first bar of a session include what was traded by the
+/// skipped quotes of the same session.
+pub fn aggregate_session(quotes: &[StockQuote], interval: TimeDelta, calendar: &Calendar) -> Vec<Bar> {
+    let quotes: Vec<StockQuote> = quotes
+        .iter()
+        .filter(|x| match x.time {
+            Some(time) => calendar.phase(x.date.and_time(time)).is_open(),
+            None => calendar.is_trading_day(x.date),
+        })
+        .cloned()
+        .collect();
+
+    aggregate(&quotes, interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+
+    fn quote(time: Option<(u32, u32)>, last: f64, volume: u64) -> StockQuote {
+        StockQuote {
+            ticker: String::from("ACS"),
+            date: NaiveDate::from_ymd_opt(2024, 2, 6).unwrap(),
+            time: time.map(|(h, m)| NaiveTime::from_hms_opt(h, m, 0).unwrap()),
+            last,
+            volume,
+            turnover: volume as f64 * last,
+        }
+    }
+
+    #[rstest]
+    #[case("30s", Some(TimeDelta::seconds(30)))]
+    #[case("5m", Some(TimeDelta::minutes(5)))]
+    #[case("15", Some(TimeDelta::minutes(15)))]
+    #[case("1h", Some(TimeDelta::hours(1)))]
+    #[case("0m", None)]
+    #[case("5d", None)]
+    #[case("m", None)]
+    fn test_parse_interval(#[case] value: &str, #[case] expected: Option<TimeDelta>) {
+        assert_eq!(parse_interval(value), expected);
+    }
+
+    #[rstest]
+    fn test_aggregate() {
+        let quotes = vec![
+            quote(Some((9, 12)), 36.0, 1000),
+            quote(Some((9, 3)), 35.0, 400),
+            quote(Some((9, 6)), 37.0, 800),
+            quote(Some((9, 14)), 35.5, 1500),
+            quote(None, 36.5, 5000),
+        ];
+
+        let bars = aggregate(&quotes, TimeDelta::minutes(10));
+        assert_eq!(bars.len(), 3);
+
+        assert_eq!(bars[0].start, NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+        assert_eq!((bars[0].open, bars[0].high, bars[0].low, bars[0].close), (35.0, 37.0, 35.0, 37.0));
+        assert_eq!(bars[0].volume, 800);
+
+        assert_eq!(bars[1].start, NaiveTime::from_hms_opt(9, 10, 0).unwrap());
+        assert_eq!((bars[1].open, bars[1].close), (36.0, 35.5));
+        assert_eq!(bars[1].volume, 700);
+
+        // The closing price is placed at the end of the closing auction.
+        assert_eq!(bars[2].start, NaiveTime::from_hms_opt(17, 30, 0).unwrap());
+        assert_eq!(bars[2].volume, 3500);
+        assert_eq!(bars.iter().map(|x| x.volume).sum::<u64>(), 5000);
+    }
+
+    #[rstest]
+    fn test_aggregate_session() {
+        let mut holiday = quote(None, 36.0, 5000);
+        holiday.date = NaiveDate::from_ymd_opt(2024, 2, 10).unwrap();
+        let quotes = vec![
+            quote(Some((8, 10)), 35.0, 0),
+            quote(Some((9, 3)), 35.5, 400),
+            quote(Some((18, 0)), 36.0, 5000),
+            quote(None, 36.5, 5000),
+            holiday,
+        ];
+
+        let bars = aggregate_session(&quotes, TimeDelta::minutes(5), &Calendar::new());
+
+        assert_eq!(bars.iter().map(|x| x.start.hour()).collect::<Vec<u32>>(), vec![9, 17]);
+        assert_eq!(bars[1].close, 36.5);
+        assert_eq!(aggregate(&quotes, TimeDelta::minutes(5)).len(), 5);
+    }
+
+    // The session of the 24th of December ends at 14:05.
+    #[rstest]
+    fn test_aggregate_short_session() {
+        let quotes: Vec<StockQuote> = [
+            quote(Some((13, 50)), 35.0, 1000),
+            quote(Some((15, 0)), 35.5, 1000),
+            quote(None, 36.0, 1500),
+        ]
+            .into_iter()
+            .map(|x| StockQuote { date: NaiveDate::from_ymd_opt(2024, 12, 24).unwrap(), ..x })
+            .collect();
+
+        let bars = aggregate_session(&quotes, TimeDelta::minutes(5), &Calendar::new());
+
+        assert_eq!(bars.iter().map(|x| x.start).collect::<Vec<NaiveTime>>(), vec![
+            NaiveTime::from_hms_opt(13, 50, 0).unwrap(),
+            NaiveTime::from_hms_opt(14, 5, 0).unwrap(),
+        ]);
+        assert_eq!((bars[1].close, bars[1].volume), (36.0, 500));
+    }
+
+    // The volume is accumulated for each stock and session.
+    #[rstest]
+    fn test_aggregate_sessions() {
+        let mut next_day = quote(Some((9, 5)), 36.0, 300);
+        next_day.date = next_day.date.succ_opt().unwrap();
+        let mut other = quote(Some((9, 5)), 3.7, 200);
+        other.ticker = String::from("B.SANTANDER");
+
+        let bars = aggregate(&[quote(Some((9, 5)), 35.0, 1000), next_day, other], TimeDelta::minutes(5));
+
+        assert_eq!(bars.len(), 3);
+        assert_eq!(bars.iter().map(|x| x.volume).collect::<Vec<u64>>(), vec![1000, 300, 200]);
+        assert_eq!(bars[2].ticker, "B.SANTANDER");
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright 2024 Felipe Torres González
+
+//! Parallel parsing of many data files.
+//!
+//! Backfilling a long period means parsing tens of thousands of data files. Each file is
+//! parsed on its own, so the work is split among a pool of threads. The results are always
+//! returned in the same order as the files were given, so the output doesn't depend on the
+//! number of threads.
+
+use std::fs::read;
+use std::path::PathBuf;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use crate::parser_ibex::IbexParser;
+
+/// Number of items given to each thread before the results are handed over, so the
+/// results of a large batch are not kept in memory until the end.
+const ITEMS_X_THREAD: usize = 16;
+
+/// A pool of threads that parses data files concurrently.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::batch::Batch;
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use std::path::PathBuf;
+///
+/// let paths = vec![
+///     PathBuf::from("./tests/data/data_ibex.csv"),
+///     PathBuf::from("./tests/data/data_ibex.html"),
+/// ];
+///
+/// let batch = Batch::new(Some(2));
+/// let data = batch.parse_files(&IbexParser::new(), &paths);
+/// assert_eq!(data.len(), 2);
+/// assert_eq!(data[0], data[1]);
+/// ```
+pub struct Batch {
+    pool: ThreadPool,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Batch {
+    /// Build a pool with the given number of threads. `None` uses as many threads as CPUs.
+    pub fn new(jobs: Option<usize>) -> Batch {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(jobs.unwrap_or(0))
+            .build()
+            .expect("Couldn't start the threads");
+
+        Batch { pool }
+    }
+
+    /// Number of threads of the pool.
+    pub fn jobs(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Apply `f` to each item concurrently.
+    ///
+    /// ## Return
+    ///
+    /// The results, in the same order as the items.
+    pub fn map<I, T, F>(&self, items: &[I], f: F) -> Vec<T>
+    where
+        I: Sync,
+        T: Send,
+        F: Fn(&I) -> T + Sync,
+    {
+        self.pool.install(|| items.par_iter().map(&f).collect())
+    }
+
+    /// Apply `f` to each item concurrently, and call `sink` with each result in the same
+    /// order as the items.
+    ///
+    /// # Description
+    ///
+    /// Items are processed in chunks, and `sink` is called with the results of a chunk
+    /// before the next chunk is processed. This way, the results of a large batch are handed
+    /// over as soon as possible, and they are not kept in memory until the end.
+    pub fn for_each_ordered<I, T, F, S>(&self, items: &[I], f: F, mut sink: S)
+    where
+        I: Sync,
+        T: Send,
+        F: Fn(&I) -> T + Sync,
+        S: FnMut(&I, T),
+    {
+        for chunk in items.chunks(self.jobs() * ITEMS_X_THREAD) {
+            for (item, result) in chunk.iter().zip(self.map(chunk, &f)) {
+                sink(item, result);
+            }
+        }
+    }
+
+    /// Parse data files concurrently with the same parser.
+    ///
+    /// ## Return
+    ///
+    /// The result of [IbexParser::parse_bytes] for each file, in the same order as the paths.
+    /// Files that can't be read result in `None`.
+    pub fn parse_files(&self, parser: &IbexParser, paths: &[PathBuf]) -> Vec<Option<Vec<String>>> {
+        self.map(paths, |x| read(x).ok().and_then(|data| parser.parse_bytes(&data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[rstest]
+    #[case(Some(1))]
+    #[case(Some(4))]
+    #[case(None)]
+    fn test_batch_parse_files(#[case] jobs: Option<usize>) {
+        let parser = IbexParser::new();
+        let paths: Vec<PathBuf> = [
+            "./tests/data/data_ibex.csv",
+            "./tests/data/wdata_ibex.csv",
+            "./tests/data/data_ibex_win.csv",
+            "./tests/data/non_existing.csv",
+        ].iter().map(PathBuf::from).collect();
+
+        let expected: Vec<Option<Vec<String>>> = paths
+            .iter()
+            .map(|x| read(x).ok().and_then(|data| parser.parse_bytes(&data)))
+            .collect();
+
+        assert_eq!(Batch::new(jobs).parse_files(&parser, &paths), expected);
+        assert!(expected[0].is_some() && expected[1].is_none() && expected[3].is_none());
+    }
+
+    // Results keep the order of the items, even when later items are done first.
+    #[rstest]
+    fn test_batch_for_each_ordered() {
+        let batch = Batch::new(Some(4));
+        let items: Vec<u64> = (0..200).collect();
+        let mut results = Vec::new();
+
+        batch.for_each_ordered(
+            &items,
+            |x| {
+                std::thread::sleep(Duration::from_micros(200 - x));
+                x * 2
+            },
+            |x, result| results.push((*x, result)),
+        );
+
+        assert_eq!(results, items.iter().map(|x| (*x, x * 2)).collect::<Vec<(u64, u64)>>());
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2024 Felipe Torres González
+
+//! Trading calendar of BME.
+//!
+//! The stocks of the Spanish market are traded from Monday to Friday, except on the market
+//! holidays: New Year's Day, Good Friday, Easter Monday, Labour Day, Christmas Day and Boxing
+//! Day. A session goes through these phases (local time of Madrid):
+//!
+//! - Opening auction, from 08:30 to 09:00.
+//! - Continuous trading, from 09:00 to 17:30.
+//! - Closing auction, from 17:30 to 17:35.
+//!
+//! On the 24th and the 31st of December the session is shorter: the continuous trading ends
+//! at 14:00, and the closing auction at 14:05.
+//!
+//! Pages saved on other days or at other times show stale data, e.g. the closing prices of
+//! the last session. A [Calendar] tells them apart (see [Snapshot::phase][crate::snapshot::Snapshot::phase]).
+//! The auctions end at a random instant within the following 30 seconds, which is ignored.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+/// Beginning of the opening auction.
+pub const AUCTION_TIME: NaiveTime = NaiveTime::from_hms_opt(8, 30, 0).unwrap();
+/// Beginning of the continuous trading.
+pub const OPEN_TIME: NaiveTime = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+/// End of the continuous trading, and beginning of the closing auction.
+pub const CONTINUOUS_END: NaiveTime = NaiveTime::from_hms_opt(17, 30, 0).unwrap();
+/// End of the closing auction, and time at which the closing prices are placed.
+pub const CLOSE_TIME: NaiveTime = NaiveTime::from_hms_opt(17, 35, 0).unwrap();
+/// End of the continuous trading on the 24th and 31st of December.
+pub const SHORT_CONTINUOUS_END: NaiveTime = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
+/// End of the closing auction on the 24th and 31st of December.
+pub const SHORT_CLOSE_TIME: NaiveTime = NaiveTime::from_hms_opt(14, 5, 0).unwrap();
+
+/// Phase of the market at some instant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// A weekend or a market holiday.
+    NonTradingDay,
+    /// A trading day, before the opening auction.
+    BeforeOpen,
+    OpeningAuction,
+    ContinuousTrading,
+    ClosingAuction,
+    /// A trading day, after the closing auction.
+    AfterClose,
+}
+
+impl Phase {
+    /// Whether prices are set during the phase: the auctions and the continuous trading.
+    pub fn is_open(&self) -> bool {
+        matches!(self, Phase::OpeningAuction | Phase::ContinuousTrading | Phase::ClosingAuction)
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::NonTradingDay => write!(f, "non-trading day"),
+            Phase::BeforeOpen => write!(f, "before the opening auction"),
+            Phase::OpeningAuction => write!(f, "opening auction"),
+            Phase::ContinuousTrading => write!(f, "continuous trading"),
+            Phase::ClosingAuction => write!(f, "closing auction"),
+            Phase::AfterClose => write!(f, "after the closing auction"),
+        }
+    }
+}
+
+/// Hours of a trading session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    /// Day of the session.
+    pub date: NaiveDate,
+    /// Beginning of the opening auction.
+    pub auction: NaiveTime,
+    /// Beginning of the continuous trading.
+    pub open: NaiveTime,
+    /// End of the continuous trading, and beginning of the closing auction.
+    pub close: NaiveTime,
+    /// End of the closing auction.
+    pub end: NaiveTime,
+}
+
+impl Session {
+    /// Phase of the session at some time. The end of the closing auction still belongs to
+    /// it, as the closing prices are placed there.
+    pub fn phase(&self, time: NaiveTime) -> Phase {
+        if time < self.auction {
+            Phase::BeforeOpen
+        } else if time < self.open {
+            Phase::OpeningAuction
+        } else if time < self.close {
+            Phase::ContinuousTrading
+        } else if time <= self.end {
+            Phase::ClosingAuction
+        } else {
+            Phase::AfterClose
+        }
+    }
+}
+
+/// Time at which the closing prices of a date are placed: the end of its closing auction,
+/// i.e. [SHORT_CLOSE_TIME] on the 24th and 31st of December, and [CLOSE_TIME] otherwise (see
+/// [Calendar::session]). The exceptional closures of the market don't change it, so the usual
+/// calendar is used.
+pub fn close_time(date: NaiveDate) -> NaiveTime {
+    Calendar::new().session(date).map_or(CLOSE_TIME, |x| x.end)
+}
+
+/// Date of Easter Sunday of a year of the Gregorian calendar (anonymous Gregorian algorithm).
+fn easter(year: i32) -> NaiveDate {
+    let (a, b, c) = (year % 19, year / 100, year % 100);
+    let (d, e) = (b / 4, b % 4);
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let (i, k) = (c / 4, c % 4);
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+/// Trading calendar of BME.
+///
+/// The market holidays are known for any year. Exceptional closures can be added with
+/// [Calendar::with_holidays].
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::calendar::{Calendar, Phase};
+/// use chrono::NaiveDate;
+///
+/// let calendar = Calendar::new();
+/// let good_friday = NaiveDate::from_ymd_opt(2024, 3, 29).unwrap();
+/// let monday = NaiveDate::from_ymd_opt(2024, 2, 5).unwrap();
+///
+/// assert!(!calendar.is_trading_day(good_friday));
+/// assert_eq!(calendar.phase(monday.and_hms_opt(10, 0, 0).unwrap()), Phase::ContinuousTrading);
+/// assert_eq!(calendar.phase(monday.and_hms_opt(18, 0, 0).unwrap()), Phase::AfterClose);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Calendar {
+    /// Closures of the market besides the usual holidays.
+    closures: BTreeSet<NaiveDate>,
+}
+
+impl Calendar {
+    /// Build the calendar with the usual market holidays.
+    pub fn new() -> Calendar {
+        Calendar::default()
+    }
+
+    /// Add exceptional closures of the market.
+    pub fn with_holidays(mut self, dates: impl IntoIterator<Item = NaiveDate>) -> Calendar {
+        self.closures.extend(dates);
+        self
+    }
+
+    /// Whether the market is closed all day because of a holiday. Weekends are not holidays.
+    pub fn is_holiday(&self, date: NaiveDate) -> bool {
+        let easter = easter(date.year());
+
+        matches!((date.month(), date.day()), (1, 1) | (5, 1) | (12, 25) | (12, 26))
+            || date == easter - Days::new(2)
+            || date == easter + Days::new(1)
+            || self.closures.contains(&date)
+    }
+
+    /// Whether the market opens on a date.
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.is_holiday(date)
+    }
+
+    /// First trading day after a date.
+    pub fn next_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        date.iter_days().skip(1).find(|x| self.is_trading_day(*x)).unwrap()
+    }
+
+    /// Last trading day before a date.
+    pub fn previous_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        let mut date = date.pred_opt().unwrap();
+        while !self.is_trading_day(date) {
+            date = date.pred_opt().unwrap();
+        }
+        date
+    }
+
+    /// Hours of the session of a date. `None` when the market doesn't open.
+    pub fn session(&self, date: NaiveDate) -> Option<Session> {
+        if !self.is_trading_day(date) {
+            return None;
+        }
+
+        let short = date.month() == 12 && matches!(date.day(), 24 | 31);
+
+        Some(Session {
+            date,
+            auction: AUCTION_TIME,
+            open: OPEN_TIME,
+            close: if short { SHORT_CONTINUOUS_END } else { CONTINUOUS_END },
+            end: if short { SHORT_CLOSE_TIME } else { CLOSE_TIME },
+        })
+    }
+
+    /// Phase of the market at some instant, given as local time of Madrid.
+    pub fn phase(&self, at: NaiveDateTime) -> Phase {
+        self.session(at.date()).map_or(Phase::NonTradingDay, |x| x.phase(at.time()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[rstest]
+    #[case(2019, date(2019, 4, 21))]
+    #[case(2024, date(2024, 3, 31))]
+    #[case(2025, date(2025, 4, 20))]
+    #[case(2026, date(2026, 4, 5))]
+    #[case(2038, date(2038, 4, 25))]
+    fn test_easter(#[case] year: i32, #[case] expected: NaiveDate) {
+        assert_eq!(easter(year), expected);
+    }
+
+    #[rstest]
+    #[case(date(2024, 1, 1), false)]
+    #[case(date(2024, 1, 2), true)]
+    #[case(date(2024, 1, 6), false)]
+    #[case(date(2024, 3, 29), false)]
+    #[case(date(2024, 4, 1), false)]
+    #[case(date(2024, 4, 2), true)]
+    #[case(date(2024, 5, 1), false)]
+    #[case(date(2024, 8, 15), true)]
+    #[case(date(2024, 12, 24), true)]
+    #[case(date(2024, 12, 25), false)]
+    #[case(date(2024, 12, 26), false)]
+    #[case(date(2025, 4, 18), false)]
+    #[case(date(2025, 4, 21), false)]
+    fn test_is_trading_day(#[case] date: NaiveDate, #[case] expected: bool) {
+        assert_eq!(Calendar::new().is_trading_day(date), expected);
+    }
+
+    #[rstest]
+    fn test_closures() {
+        let calendar = Calendar::new().with_holidays([date(2024, 2, 6)]);
+
+        assert!(calendar.is_holiday(date(2024, 2, 6)));
+        assert_eq!(calendar.next_trading_day(date(2024, 2, 5)), date(2024, 2, 7));
+        assert_eq!(calendar.previous_trading_day(date(2024, 2, 7)), date(2024, 2, 5));
+        assert_eq!(calendar.next_trading_day(date(2024, 3, 28)), date(2024, 4, 2));
+        assert_eq!(calendar.previous_trading_day(date(2024, 4, 2)), date(2024, 3, 28));
+    }
+
+    #[rstest]
+    #[case(date(2024, 2, 6), CLOSE_TIME)]
+    #[case(date(2024, 2, 10), CLOSE_TIME)]
+    #[case(date(2024, 12, 24), SHORT_CLOSE_TIME)]
+    #[case(date(2024, 12, 31), SHORT_CLOSE_TIME)]
+    fn test_close_time(#[case] date: NaiveDate, #[case] expected: NaiveTime) {
+        assert_eq!(close_time(date), expected);
+    }
+
+    #[rstest]
+    #[case(date(2024, 2, 6), (8, 0), Phase::BeforeOpen)]
+    #[case(date(2024, 2, 6), (8, 45), Phase::OpeningAuction)]
+    #[case(date(2024, 2, 6), (9, 0), Phase::ContinuousTrading)]
+    #[case(date(2024, 2, 6), (17, 30), Phase::ClosingAuction)]
+    #[case(date(2024, 2, 6), (17, 35), Phase::ClosingAuction)]
+    #[case(date(2024, 2, 6), (17, 36), Phase::AfterClose)]
+    #[case(date(2024, 2, 10), (12, 0), Phase::NonTradingDay)]
+    #[case(date(2024, 12, 24), (13, 59), Phase::ContinuousTrading)]
+    #[case(date(2024, 12, 24), (14, 5), Phase::ClosingAuction)]
+    #[case(date(2024, 12, 31), (15, 0), Phase::AfterClose)]
+    fn test_phase(#[case] date: NaiveDate, #[case] time: (u32, u32), #[case] expected: Phase) {
+        let phase = Calendar::new().phase(date.and_hms_opt(time.0, time.1, 0).unwrap());
+
+        assert_eq!(phase, expected);
+        assert_eq!(phase.is_open(), !matches!(expected, Phase::NonTradingDay | Phase::BeforeOpen | Phase::AfterClose));
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright 2024 Felipe Torres González
+
+//! Automatic detection of the layout of a data file.
+//!
+//! BME changes the design of its page from time to time, and the data files of a long
+//! period of time might not share the same layout. This module fingerprints the content
+//! of a data file to pick the layout that matches it among a set of candidates.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use crate::html::{is_html, text_lines};
+use crate::layout::{Bounds, Column, ColumnRef, Layout};
+use crate::normalize::decode;
+use crate::parser_ibex::{IbexParser, Mode};
+
+/// How well a layout matches the content of a data file.
+///
+/// Scores are compared field by field, in declaration order: a higher score means a better
+/// match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Score {
+    /// Whether the layout parses a table for an index.
+    pub index: bool,
+    /// Length of the title of the layout when it is found in the content, 0 otherwise.
+    /// Longer titles are more specific, e.g. "IBEX 35 BANCOS" over "IBEX 35".
+    pub title: usize,
+    /// Whether the footer of the layout is found in the content.
+    pub footer: bool,
+}
+
+/// Compute the score of a layout for some content.
+///
+/// # Description
+///
+/// A layout only matches the content when the content can be parsed with it, i.e. the
+/// headers of the tables are found, the columns of the layout are found in the tables, and
+/// the values have the type of their columns. The content is parsed in [Mode::Lenient]
+/// whatever the mode of `parser`, so a broken row doesn't hide the layout of the content,
+/// and the row is rather reported when the content is parsed with the detected layout. When
+/// the layout matches, its title and footer are searched at the beginning of the lines of the
+/// content.
+///
+/// ## Arguments
+///
+/// - `parser` a parser that uses the layout to check.
+/// - `content` the normalised content of a data file (see [decode]).
+///
+/// ## Return
+///
+/// `None` when the layout doesn't match the content.
+///
+/// This check parses the entire content, so [Detector] rather picks the layout using [sniff],
+/// and the content is only parsed once with the detected layout.
+pub fn score(parser: &IbexParser, content: &str) -> Option<Score> {
+    match parser.mode() {
+        Mode::Lenient => parser.parse_str(content)?,
+        Mode::Strict => IbexParser::with_layout(parser.layout().clone()).parse_str(content)?,
+    };
+
+    let layout = parser.layout();
+    let lines: Vec<String> = if is_html(content) {
+        text_lines(content)
+    } else {
+        content.lines().map(String::from).collect()
+    };
+    let found = |x: &str| lines.iter().any(|l| l.starts_with(x));
+    let title = lines.iter().any(|l| layout.matches_title(l));
+
+    Some(Score {
+        index: layout.index_header.is_some(),
+        title: if title { layout.title.trim().len() } else { 0 },
+        footer: found(&layout.footer),
+    })
+}
+
+/// Number of bytes at the beginning of a text file that are inspected by [sniff].
+pub const SNIFF_BYTES: usize = 4096;
+
+/// Check cheaply whether the raw content of a file is a data file of one of some layouts.
+///
+/// # Description
+///
+/// Rather than parsing the entire file, only the first lines of a text file are inspected
+/// (up to [SNIFF_BYTES]): a layout matches when the header of each of its tables is found at
+/// the beginning of a line, followed by the names of its columns. HTML pages are inspected
+/// in full, as their tables are found after a long head, and a layout matches when the names
+/// of the headers and columns of its tables are found in the markup.
+///
+/// Hence, the size of the file doesn't matter: a sector page with a single stock is accepted,
+/// and a large file that doesn't contain stock data is not.
+///
+/// The rows of layouts with [Bounds::Fixed] are found by their position, whatever the headers
+/// of the tables, so the headers are not checked for them: such a layout matches when its
+/// title is found in the content.
+///
+/// When several layouts match the content, the best one is picked as in [score], but only
+/// using the title of the layout.
+///
+/// ## Return
+///
+/// The layout whose tables are found in the content, or `None` when the content is not a data
+/// file of any of the given layouts. Matching a layout doesn't mean that the data file is
+/// valid, e.g. a file with wrong values is still rejected by the parser.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::detect::sniff;
+/// use ibex_parser::layout::Layout;
+/// use std::fs::read;
+///
+/// let layouts = [Layout::ibex35(), Layout::mercado_continuo()];
+/// let data = read("./tests/data/layouts/data_mercado_continuo.csv").unwrap();
+///
+/// assert_eq!(sniff(&data, &layouts).unwrap().name, "mercado-continuo");
+/// assert!(sniff(b"Mercado Continuo\nNombre\tVolumen\n", &layouts).is_none());
+/// ```
+pub fn sniff<'a>(data: &[u8], layouts: impl IntoIterator<Item = &'a Layout>) -> Option<&'a Layout> {
+    // Only entire lines are decoded, so a character is never split.
+    let head = match data.get(..SNIFF_BYTES) {
+        Some(x) => &x[..x.iter().rposition(|c| *c == b'\n').map_or(0, |i| i + 1)],
+        None => data,
+    };
+    let mut content = decode(head);
+    let html = is_html(&content);
+
+    if html {
+        content = decode(data);
+    }
+
+    let lines: Vec<&str> = content.lines().collect();
+    let names = |columns: &'a [Column]| columns.iter().filter_map(|x| match &x.source {
+        ColumnRef::Name(name) => Some(name.as_str()),
+        ColumnRef::Index(_) => None,
+    });
+    let has_table = |header: &str, columns: &'a [Column]| {
+        if html {
+            return content.contains(header) && names(columns).all(|x| content.contains(x));
+        }
+
+        lines.iter().any(|x| {
+            let cells: Vec<&str> = x.split('\t').collect();
+            cells.len() > 1 && cells[0] == header && names(columns).all(|x| cells.contains(&x))
+        })
+    };
+    // The title is found among the text of the page, rather than in the markup.
+    let text = if html { text_lines(&content) } else { Vec::new() };
+    let has_title = |layout: &Layout| if html {
+        text.iter().any(|l| layout.matches_title(l))
+    } else {
+        lines.iter().any(|l| layout.matches_title(l))
+    };
+
+    let mut best: Option<((bool, usize), &Layout)> = None;
+
+    for layout in layouts {
+        let title = has_title(layout);
+        let index = if let Bounds::Fixed { .. } = layout.bounds {
+            if !title {
+                continue;
+            }
+            layout.index_header.is_some()
+        } else {
+            let index = match &layout.index_header {
+                Some(x) if !has_table(x, &layout.index_columns) => continue,
+                x => x.is_some(),
+            };
+
+            if !has_table(&layout.stock_header, &layout.stock_columns) {
+                continue;
+            }
+            index
+        };
+
+        let score = (index, if title { layout.title.trim().len() } else { 0 });
+        if best.is_none_or(|(s, _)| score > s) {
+            best = Some((score, layout));
+        }
+    }
+
+    best.map(|(_, layout)| layout)
+}
+
+/// Check cheaply whether a file is a data file of one of some layouts.
+///
+/// Only the first [SNIFF_BYTES] of a text file are read, so large files that don't contain
+/// stock data are discarded quickly. See [sniff] for the details.
+///
+/// ## Return
+///
+/// The layout whose tables are found in the file, `None` if none, or an error if the file
+/// can't be read.
+pub fn sniff_file<'a>(
+    path: &Path,
+    layouts: impl IntoIterator<Item = &'a Layout>
+) -> io::Result<Option<&'a Layout>> {
+    let mut file = File::open(path)?;
+    let mut data = Vec::new();
+    file.by_ref().take(SNIFF_BYTES as u64).read_to_end(&mut data)?;
+
+    // HTML pages are inspected in full.
+    if is_html(&decode(&data)) {
+        file.read_to_end(&mut data)?;
+    }
+
+    Ok(sniff(&data, layouts))
+}
+
+/// A set of layouts from which the layout of each data file is detected.
+///
+/// # Description
+///
+/// The detector keeps a parser for each candidate layout. When some content is given, the
+/// layout is picked from the first lines of the content, as in [sniff], rather than parsing
+/// the content with each candidate, so the content is only parsed once with the parser that
+/// is returned. On ties, the layout that was given first wins, and user profiles are always
+/// given before the built-in layouts.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::detect::Detector;
+/// use std::fs::read;
+///
+/// let detector = Detector::new();
+/// let data = read("./tests/data/layouts/data_ibex35_bancos.csv").unwrap();
+///
+/// let parser = detector.detect_bytes(&data).unwrap();
+/// assert_eq!(parser.layout().name, "ibex35-sector");
+/// println!("{:?}", parser.parse_bytes(&data));
+/// ```
+pub struct Detector {
+    parsers: Vec<IbexParser>,
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Detector {
+    /// Build a detector whose candidates are the built-in layouts.
+    pub fn new() -> Detector {
+        Detector::with_profiles(Vec::new())
+    }
+
+    /// Build a detector whose candidates are the given layouts and the built-in layouts.
+    ///
+    /// The given layouts are usually loaded from profile files (see
+    /// [profile::load][crate::profile::load]), and they take precedence over the built-in
+    /// layouts on ties.
+    pub fn with_profiles(profiles: Vec<Layout>) -> Detector {
+        let builtins = Layout::builtin_names().into_iter().filter_map(Layout::builtin);
+
+        Detector {
+            parsers: profiles.into_iter().chain(builtins).map(IbexParser::with_layout).collect(),
+        }
+    }
+
+    /// Set how the parsers of the detector deal with the rows that can't be parsed (see
+    /// [IbexParser::with_mode]).
+    pub fn with_mode(mut self, mode: Mode) -> Detector {
+        self.parsers = self.parsers.into_iter().map(|x| x.with_mode(mode)).collect();
+        self
+    }
+
+    /// Candidate layouts, in order of precedence.
+    pub fn layouts(&self) -> impl Iterator<Item = &Layout> {
+        self.parsers.iter().map(|x| x.layout())
+    }
+
+    /// Check cheaply whether the raw content of a file is a data file of any of the candidate
+    /// layouts (see [sniff]).
+    pub fn sniff(&self, data: &[u8]) -> Option<&Layout> {
+        sniff(data, self.layouts())
+    }
+
+    /// Pick the parser whose layout matches the best some normalised content.
+    ///
+    /// `None` is returned when no layout matches the content. Matching a layout doesn't mean
+    /// that the content is valid, e.g. a file with wrong values is still rejected by the
+    /// parser.
+    pub fn detect(&self, content: &str) -> Option<&IbexParser> {
+        self.detect_bytes(content.as_bytes())
+    }
+
+    /// Pick the parser whose layout matches the best the raw content of a data file.
+    pub fn detect_bytes(&self, data: &[u8]) -> Option<&IbexParser> {
+        let layout = self.sniff(data)?;
+
+        self.parsers.iter().find(|x| std::ptr::eq(x.layout(), layout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use std::fs::{read, read_to_string};
+    use std::path::Path;
+    use crate::profile;
+
+    #[rstest]
+    #[case("./tests/data/data_ibex.csv", "ibex35")]
+    #[case("./tests/data/data_ibex_win.csv", "ibex35")]
+    #[case("./tests/data/data_ibex.html", "ibex35")]
+    #[case("./tests/data/layouts/data_ibex_medium_cap.csv", "ibex-medium-cap")]
+    #[case("./tests/data/layouts/data_ibex35_bancos.csv", "ibex35-sector")]
+    #[case("./tests/data/layouts/data_mercado_continuo.csv", "mercado-continuo")]
+    fn test_detector_builtin(#[case] path: &str, #[case] expected: &str) {
+        let detector = Detector::new();
+        let data = read(path).unwrap();
+
+        assert_eq!(detector.detect_bytes(&data).unwrap().layout().name, expected);
+    }
+
+    // A broken row doesn't hide the layout of a file from a strict detector, so the row is
+    // reported when the file is parsed.
+    #[rstest]
+    fn test_detector_strict() {
+        let content = read_to_string("./tests/data/data_ibex.csv").unwrap().replacen("Cierre", "-", 1);
+        let detector = Detector::new().with_mode(Mode::Strict);
+        let parser = detector.detect(&content).unwrap();
+
+        assert_eq!(parser.layout().name, "ibex35");
+        assert_eq!(parser.try_with_records(&content, |x| x.len()).unwrap_err().row(), Some(1));
+    }
+
+    #[rstest]
+    fn test_detector_nomatch() {
+        let detector = Detector::new();
+        let data = read("./tests/data/wdata_ibex.csv").unwrap();
+
+        assert!(detector.detect_bytes(&data).is_none());
+    }
+
+    // A file with another layout is only detected when its profile is given. The layout is
+    // synthetic (see tests/data/profiles/ibex35_legacy.toml), it's not an actual page of BME.
+    #[rstest]
+    fn test_detector_profile() {
+        let data = read("./tests/data/layouts/data_ibex_legacy.csv").unwrap();
+        let legacy = profile::load(Path::new("./tests/data/profiles/ibex35_legacy.toml")).unwrap();
+
+        assert!(Detector::new().detect_bytes(&data).is_none());
+
+        let detector = Detector::with_profiles(vec![legacy]);
+        let parser = detector.detect_bytes(&data).unwrap();
+        assert_eq!(parser.layout().name, "ibex35-legacy");
+        assert_eq!(parser.parse_bytes(&data).unwrap().len(), 36);
+
+        // Files with the current layout are still detected.
+        let data = read("./tests/data/data_ibex.csv").unwrap();
+        assert_eq!(detector.detect_bytes(&data).unwrap().layout().name, "ibex35");
+    }
+
+    #[rstest]
+    #[case("./tests/data/data_ibex.csv", Some("ibex35"))]
+    #[case("./tests/data/data_ibex_win.csv", Some("ibex35"))]
+    #[case("./tests/data/data_ibex.html", Some("ibex35"))]
+    #[case("./tests/data/layouts/data_ibex35_bancos.csv", Some("ibex35-sector"))]
+    #[case("./tests/data/layouts/data_mercado_continuo.csv", Some("mercado-continuo"))]
+    #[case("./tests/data/layouts/data_ibex_legacy.csv", None)]
+    #[case("./tests/data/wdata_ibex.csv", None)]
+    fn test_sniff(#[case] path: &str, #[case] expected: Option<&str>) {
+        let data = read(path).unwrap();
+
+        assert_eq!(Detector::new().sniff(&data).map(|x| x.name.as_str()), expected);
+    }
+
+    // The size of the file is not what makes it a data file.
+    #[rstest]
+    fn test_sniff_size() {
+        let content = read_to_string("./tests/data/layouts/data_ibex35_bancos.csv").unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        // A sector page with a single stock, and no notes but the first one.
+        let small = [&lines[..12], &lines[17..19]].concat().join("\n");
+        let large = "Nombre\tÚltimo\n".repeat(1000);
+
+        assert!(small.len() < content.len() && large.len() > SNIFF_BYTES);
+        assert_eq!(Detector::new().sniff(small.as_bytes()).unwrap().name, "ibex35-sector");
+        assert_eq!(Detector::new().detect(&small).unwrap().parse_str(&small).unwrap().len(), 2);
+        assert!(Detector::new().sniff(large.as_bytes()).is_none());
+    }
+
+    // The headers of the tables are not checked for layouts with fixed bounds.
+    #[rstest]
+    #[case("./tests/data/data_ibex.csv", true)]
+    #[case("./tests/data/layouts/data_ibex_legacy.csv", true)]
+    #[case("./tests/data/layouts/data_mercado_continuo.csv", false)]
+    fn test_sniff_fixed(#[case] path: &str, #[case] expected: bool) {
+        let layout = Layout {
+            stock_header: String::from("Código"),
+            bounds: Bounds::Fixed { skip_beg: 11, index_line: 6, skip_end: 5 },
+            ..Layout::ibex35()
+        };
+
+        assert_eq!(sniff(&read(path).unwrap(), [&layout]).is_some(), expected);
+    }
+
+    #[rstest]
+    #[case("./tests/data/data_ibex.html", Some("ibex35"))]
+    #[case("./tests/data/layouts/data_mercado_continuo.csv", Some("mercado-continuo"))]
+    #[case("./tests/data/archives/snapshots.zip", None)]
+    fn test_sniff_file(#[case] path: &str, #[case] expected: Option<&str>) {
+        let detector = Detector::new();
+        let layout = sniff_file(Path::new(path), detector.layouts()).unwrap();
+
+        assert_eq!(layout.map(|x| x.name.as_str()), expected);
+    }
+
+    #[rstest]
+    fn test_score() {
+        let content = decode(&read("./tests/data/layouts/data_ibex35_bancos.csv").unwrap());
+        let ibex35 = score(&IbexParser::new(), &content).unwrap();
+        let sector = score(&IbexParser::with_layout(Layout::builtin("ibex35-sector").unwrap()), &content)
+            .unwrap();
+
+        // The page is titled "IBEX 35 BANCOS", so the title of the IBEX 35 is not found.
+        assert_eq!(ibex35, Score { index: true, title: 0, footer: true });
+        assert_eq!(sector, Score { index: true, title: 7, footer: true });
+        assert!(sector > ibex35);
+    }
+}
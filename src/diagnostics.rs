@@ -0,0 +1,322 @@
+// Copyright 2024 Felipe Torres González
+
+//! Diagnostics of the data files processed during a run.
+//!
+//! A directory of data files usually contains some files that can't be parsed: files that
+//! were saved while the page was still loading, pages of other indices, or the same data
+//! file saved twice. A [Diagnostics] collects what happened to each file, so a run can
+//! end with a report of the files that were skipped, and why.
+
+use std::fmt;
+use std::io::{self, Write};
+use serde_json::{json, Value};
+use crate::parser_ibex::ParseError;
+
+/// The reason why a data file was skipped with no attempt to parse it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The content of the file is not a data file of the layouts in use (see
+    /// [sniff][crate::detect::sniff]).
+    NotSnapshot,
+    /// The layout of the file is none of the known layouts.
+    WrongLayout,
+    /// The file has the same content as a file that was already processed, given by name.
+    Duplicate(String),
+    /// The file couldn't be read, with the reason given by the system.
+    Unreadable(String),
+}
+
+impl SkipReason {
+    /// Short name of the reason, used by the JSON report.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkipReason::NotSnapshot => "not_snapshot",
+            SkipReason::WrongLayout => "wrong_layout",
+            SkipReason::Duplicate(_) => "duplicate",
+            SkipReason::Unreadable(_) => "unreadable",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::NotSnapshot => write!(f, "no stock data found"),
+            SkipReason::WrongLayout => write!(f, "unknown layout"),
+            SkipReason::Duplicate(x) => write!(f, "duplicate of {x}"),
+            SkipReason::Unreadable(x) => write!(f, "couldn't be read: {x}"),
+        }
+    }
+}
+
+/// What happened to a data file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// The file was parsed.
+    Parsed,
+    /// The file was parsed, but its data was rejected.
+    Failed(ParseError),
+    /// The file was not parsed.
+    Skipped(SkipReason),
+}
+
+/// Diagnostics of a data file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileReport {
+    /// Name of the file. Files stored in archives are named `archive:file`.
+    pub file: String,
+    /// Name of the layout used to parse the file.
+    pub layout: Option<String>,
+    pub status: Status,
+    /// Number of rows that were parsed, including the row of the index.
+    pub rows_parsed: usize,
+    /// Number of rows that couldn't be parsed.
+    pub rows_failed: usize,
+    /// Errors of the rows that were skipped, when the file is parsed in lenient mode.
+    pub warnings: Vec<ParseError>,
+}
+
+/// Totals of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Summary {
+    pub files: usize,
+    pub parsed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub rows_parsed: usize,
+    pub rows_failed: usize,
+}
+
+/// Collector of the diagnostics of the data files processed during a run.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::diagnostics::{Diagnostics, SkipReason};
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use std::fs::read_to_string;
+///
+/// let parser = IbexParser::new();
+/// let mut diagnostics = Diagnostics::default();
+///
+/// for file in ["data_ibex.csv", "wdata_ibex.csv"] {
+///     let content = read_to_string(format!("./tests/data/{file}")).unwrap();
+///     match parser.try_with_records(&content, |x| x.len()) {
+///         Ok(rows) => diagnostics.parsed(file, "ibex35", rows, Vec::new()),
+///         Err(e) => diagnostics.failed(file, Some("ibex35"), e),
+///     }
+/// }
+/// diagnostics.skipped("empty.csv", SkipReason::NotSnapshot);
+///
+/// let summary = diagnostics.summary();
+/// assert_eq!((summary.parsed, summary.failed, summary.skipped), (1, 1, 1));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    files: Vec<FileReport>,
+}
+
+impl Diagnostics {
+    /// Record a file whose rows were parsed, along with the errors of the rows that were
+    /// skipped.
+    pub fn parsed(&mut self, file: &str, layout: &str, rows: usize, warnings: Vec<ParseError>) {
+        self.files.push(FileReport {
+            file: String::from(file),
+            layout: Some(String::from(layout)),
+            status: Status::Parsed,
+            rows_parsed: rows,
+            rows_failed: warnings.len(),
+            warnings,
+        });
+    }
+
+    /// Record a file whose data was rejected.
+    pub fn failed(&mut self, file: &str, layout: Option<&str>, error: ParseError) {
+        self.files.push(FileReport {
+            file: String::from(file),
+            layout: layout.map(String::from),
+            rows_parsed: 0,
+            rows_failed: usize::from(error.row().is_some()),
+            status: Status::Failed(error),
+            warnings: Vec::new(),
+        });
+    }
+
+    /// Record a file that was not parsed.
+    pub fn skipped(&mut self, file: &str, reason: SkipReason) {
+        self.files.push(FileReport {
+            file: String::from(file),
+            layout: None,
+            status: Status::Skipped(reason),
+            rows_parsed: 0,
+            rows_failed: 0,
+            warnings: Vec::new(),
+        });
+    }
+
+    /// The diagnostics of each file, in the same order as they were recorded.
+    pub fn files(&self) -> &[FileReport] {
+        &self.files
+    }
+
+    /// Compute the totals of the run.
+    pub fn summary(&self) -> Summary {
+        let mut summary = Summary { files: self.files.len(), ..Summary::default() };
+
+        for x in self.files.iter() {
+            match x.status {
+                Status::Parsed => summary.parsed += 1,
+                Status::Failed(_) => summary.failed += 1,
+                Status::Skipped(_) => summary.skipped += 1,
+            }
+            summary.rows_parsed += x.rows_parsed;
+            summary.rows_failed += x.rows_failed;
+        }
+
+        summary
+    }
+
+    /// Write a report with a line per file, and a last line with the totals of the run.
+    pub fn write_text<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for x in self.files.iter() {
+            match &x.status {
+                Status::Parsed => writeln!(
+                    out,
+                    "{}: parsed {} rows with the layout {}",
+                    x.file,
+                    x.rows_parsed,
+                    x.layout.as_deref().unwrap_or_default()
+                )?,
+                Status::Failed(e) => writeln!(out, "{}: failed, {e}", x.file)?,
+                Status::Skipped(reason) => writeln!(out, "{}: skipped, {reason}", x.file)?,
+            }
+
+            for e in x.warnings.iter() {
+                writeln!(out, "  skipped {e}")?;
+            }
+        }
+
+        let summary = self.summary();
+        writeln!(
+            out,
+            "{} files: {} parsed, {} failed, {} skipped. {} rows parsed, {} rows failed",
+            summary.files,
+            summary.parsed,
+            summary.failed,
+            summary.skipped,
+            summary.rows_parsed,
+            summary.rows_failed,
+        )?;
+
+        out.flush()
+    }
+
+    /// Write a report with JSON format: an object with the totals of the run (`summary`), and
+    /// an array with the diagnostics of each file (`files`).
+    ///
+    /// The `status` of a file is `parsed`, `failed` or `skipped`. Skipped files have a
+    /// `reason` (see [SkipReason::name]), and failed and skipped files have a `message`. The
+    /// errors of the rows skipped in lenient mode are found in `warnings`.
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let files: Vec<Value> = self.files
+            .iter()
+            .map(|x| {
+                let (status, reason, message) = match &x.status {
+                    Status::Parsed => ("parsed", None, None),
+                    Status::Failed(e) => ("failed", None, Some(e.to_string())),
+                    Status::Skipped(reason) => ("skipped", Some(reason.name()), Some(reason.to_string())),
+                };
+
+                json!({
+                    "file": x.file,
+                    "layout": x.layout,
+                    "status": status,
+                    "reason": reason,
+                    "message": message,
+                    "rows_parsed": x.rows_parsed,
+                    "rows_failed": x.rows_failed,
+                    "warnings": x.warnings.iter().map(|e| e.to_string()).collect::<Vec<String>>(),
+                })
+            })
+            .collect();
+        let summary = self.summary();
+
+        let report = json!({
+            "summary": {
+                "files": summary.files,
+                "parsed": summary.parsed,
+                "failed": summary.failed,
+                "skipped": summary.skipped,
+                "rows_parsed": summary.rows_parsed,
+                "rows_failed": summary.rows_failed,
+            },
+            "files": files,
+        });
+
+        serde_json::to_writer_pretty(&mut out, &report)?;
+        writeln!(out)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use crate::layout::ColumnRef;
+
+    #[fixture]
+    fn diagnostics() -> Diagnostics {
+        let mut diagnostics = Diagnostics::default();
+
+        diagnostics.parsed("data_ibex.csv", "ibex35", 35, vec![ParseError::MissingValue {
+            row: 7,
+            column: ColumnRef::Name(String::from("Volumen")),
+        }]);
+        diagnostics.failed("wdata_ibex.csv", Some("ibex35"), ParseError::WrongValue {
+            row: 3,
+            column: ColumnRef::Name(String::from("Hora")),
+            value: String::from("-"),
+        });
+        diagnostics.skipped("data_ibex_2.csv", SkipReason::Duplicate(String::from("data_ibex.csv")));
+
+        diagnostics
+    }
+
+    #[rstest]
+    fn test_diagnostics_text(diagnostics: Diagnostics) {
+        let mut out = Vec::new();
+        diagnostics.write_text(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+data_ibex.csv: parsed 35 rows with the layout ibex35
+  skipped row 7 has no column Volumen
+wdata_ibex.csv: failed, row 3 has a wrong value for the column Hora: -
+data_ibex_2.csv: skipped, duplicate of data_ibex.csv
+3 files: 1 parsed, 1 failed, 1 skipped. 35 rows parsed, 2 rows failed
+");
+    }
+
+    #[rstest]
+    fn test_diagnostics_json(diagnostics: Diagnostics) {
+        let mut out = Vec::new();
+        diagnostics.write_json(&mut out).unwrap();
+
+        let report: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(report["summary"]["rows_parsed"], 35);
+        assert_eq!(report["files"][0]["warnings"], json!(["row 7 has no column Volumen"]));
+        assert_eq!(report["files"][0]["message"], Value::Null);
+        assert_eq!(report["files"][1]["status"], "failed");
+        assert_eq!(report["files"][2], json!({
+            "file": "data_ibex_2.csv",
+            "layout": null,
+            "status": "skipped",
+            "reason": "duplicate",
+            "message": "duplicate of data_ibex.csv",
+            "rows_parsed": 0,
+            "rows_failed": 0,
+            "warnings": [],
+        }));
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2024 Felipe Torres González
+
+//! Export of the quotes to standard formats.
+//!
+//! The output of the parser keeps the format of BME's web: values split by `;`, dates as
+//! `DD/MM/YYYY`, and numbers with the Spanish format. That's fine for a spreadsheet with
+//! Spanish locale, but most tools expect something else. This module writes the typed quotes
+//! using formats that any tool understands:
+//!
+//! - CSV: values split by `,`, a header, dates as `YYYY-MM-DD`, times as `HH:MM:SS`, and
+//!   numbers with `.` as decimal mark and no thousands separator.
+//! - JSON: an array of objects with the same keys as the header of the CSV format.
+//!
+//! The time of the closing prices is empty in CSV, and `null` in JSON.
+//!
+//! Snapshots can be exported with some options (see [ExportOptions]):
+//!
+//! - The timing of each quote (see [Timing]): the date and time at which it was published
+//!   (`published_at`), the instant at which the page was captured (`captured_at`), and the lag
+//!   between both in seconds (`lag`).
+//! - The date and time of each quote as a timestamp (`timestamp`) with an explicit zone: UTC,
+//!   or the local time of Madrid with its offset (see [TimeFormat]). The closing prices are
+//!   placed at the end of the closing auction (see [close_time][crate::calendar::close_time]).
+//!   The timing is written with the same format.
+
+use std::io::{self, Write};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde_json::{json, Value};
+use crate::quote::{IndexQuote, Quotes, StockQuote};
+use crate::snapshot::{Snapshot, Timing};
+use crate::timezone::TimeFormat;
+
+/// Header of the CSV format.
+pub const CSV_HEADER: &str = "kind,ticker,date,time,last,volume,turnover";
+/// Column appended to the header of the CSV format when the timestamps are exported.
+pub const CSV_TIMESTAMP_HEADER: &str = "timestamp";
+/// Columns appended to the header of the CSV format when the timing is exported.
+pub const CSV_TIMING_HEADER: &str = "published_at,captured_at,lag";
+
+/// Options of the export of snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExportOptions {
+    /// Add the timing of each quote.
+    pub timing: bool,
+    /// Add the date and time of each quote as a timestamp with the given format. The timing
+    /// is written with the same format, or as local time when not given.
+    pub timestamps: Option<TimeFormat>,
+}
+
+/// A row of the exported data: the quote of an index or a stock.
+struct Row<'a> {
+    kind: &'a str,
+    ticker: &'a str,
+    date: NaiveDate,
+    time: Option<NaiveTime>,
+    last: f64,
+    volume: Option<u64>,
+    turnover: Option<f64>,
+    timing: Timing,
+}
+
+/// The quotes of a data file, along with the instant at which it was captured.
+type Source<'a> = (Option<&'a IndexQuote>, &'a [StockQuote], Option<NaiveDateTime>);
+
+fn rows<'a>(sources: impl Iterator<Item = Source<'a>>) -> impl Iterator<Item = Row<'a>> {
+    sources.flat_map(|(index, stocks, captured_at)| {
+        let timing = move |published_at| Timing { published_at, captured_at };
+        let index = index.into_iter().map(move |i| Row {
+            kind: "index",
+            ticker: &i.name,
+            date: i.date,
+            time: i.time,
+            last: i.last,
+            volume: None,
+            turnover: None,
+            timing: timing(i.published_at()),
+        });
+        let stocks = stocks.iter().map(move |s| Row {
+            kind: "stock",
+            ticker: &s.ticker,
+            date: s.date,
+            time: s.time,
+            last: s.last,
+            volume: Some(s.volume),
+            turnover: Some(s.turnover),
+            timing: timing(s.published_at()),
+        });
+
+        index.chain(stocks)
+    })
+}
+
+/// Quote a CSV field when it contains a separator or a quote.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|x| x.to_string()).unwrap_or_default()
+}
+
+fn quote_sources(quotes: &[Quotes]) -> impl Iterator<Item = Source<'_>> {
+    quotes.iter().map(|x| (x.index.as_ref(), x.stocks.as_slice(), None))
+}
+
+fn snapshot_sources(snapshots: &[Snapshot]) -> impl Iterator<Item = Source<'_>> {
+    snapshots.iter().map(|x| (x.index.as_ref(), x.stocks.as_slice(), x.captured_at))
+}
+
+/// Write the quotes with CSV format, including the header.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::export::write_csv;
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use std::fs::read;
+///
+/// let quotes = IbexParser::new().parse_quotes(&read("./tests/data/data_ibex.csv").unwrap()).unwrap();
+/// let mut out = Vec::new();
+/// write_csv(&mut out, &[quotes]).unwrap();
+///
+/// let csv = String::from_utf8(out).unwrap();
+/// assert!(csv.contains("stock,ACS,2024-02-06,,36.5,501552,18341960"));
+/// ```
+pub fn write_csv<W: Write>(out: W, quotes: &[Quotes]) -> io::Result<()> {
+    csv(out, rows(quote_sources(quotes)), ExportOptions::default())
+}
+
+/// Write the quotes of the snapshots with CSV format, including the header, and the columns
+/// given by the options. The lag is empty when the capture time is unknown.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::export::{write_snapshots_csv, ExportOptions};
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use ibex_parser::timezone::TimeFormat;
+/// use chrono::TimeDelta;
+/// use std::path::Path;
+///
+/// let snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+/// let captured_at = snapshot.market_at.unwrap() + TimeDelta::minutes(15);
+/// let snapshot = snapshot.with_captured_at(captured_at);
+/// let options = ExportOptions { timing: true, timestamps: Some(TimeFormat::Utc) };
+/// let mut out = Vec::new();
+/// write_snapshots_csv(&mut out, &[snapshot], options).unwrap();
+///
+/// let csv = String::from_utf8(out).unwrap();
+/// assert!(csv.contains("stock,ACS,2024-02-06,,36.5,501552,18341960,2024-02-06T16:35:00Z,\
+///                       2024-02-06T16:35:00Z,2024-02-06T16:52:03Z,1023"));
+/// ```
+pub fn write_snapshots_csv<W: Write>(out: W, snapshots: &[Snapshot], options: ExportOptions) -> io::Result<()> {
+    csv(out, rows(snapshot_sources(snapshots)), options)
+}
+
+fn csv<'a, W: Write>(mut out: W, rows: impl Iterator<Item = Row<'a>>, options: ExportOptions) -> io::Result<()> {
+    let format = options.timestamps.unwrap_or_default();
+
+    write!(out, "{CSV_HEADER}")?;
+    if options.timestamps.is_some() {
+        write!(out, ",{CSV_TIMESTAMP_HEADER}")?;
+    }
+    if options.timing {
+        write!(out, ",{CSV_TIMING_HEADER}")?;
+    }
+    writeln!(out)?;
+
+    for row in rows {
+        write!(
+            out,
+            "{},{},{},{},{},{},{}",
+            row.kind,
+            csv_field(row.ticker),
+            row.date,
+            optional(row.time),
+            row.last,
+            optional(row.volume),
+            optional(row.turnover),
+        )?;
+
+        if options.timestamps.is_some() {
+            write!(out, ",{}", format.format(row.timing.published_at))?;
+        }
+        if options.timing {
+            write!(
+                out,
+                ",{},{},{}",
+                format.format(row.timing.published_at),
+                optional(row.timing.captured_at.map(|t| format.format(t))),
+                optional(row.timing.lag().map(|d| d.num_seconds())),
+            )?;
+        }
+        writeln!(out)?;
+    }
+
+    out.flush()
+}
+
+/// Write the quotes with JSON format, as an array of objects.
+pub fn write_json<W: Write>(out: W, quotes: &[Quotes]) -> io::Result<()> {
+    json(out, rows(quote_sources(quotes)), ExportOptions::default())
+}
+
+/// Write the quotes of the snapshots with JSON format, with the keys given by the options.
+/// The lag is `null` when the capture time is unknown.
+pub fn write_snapshots_json<W: Write>(out: W, snapshots: &[Snapshot], options: ExportOptions) -> io::Result<()> {
+    json(out, rows(snapshot_sources(snapshots)), options)
+}
+
+fn json<'a, W: Write>(mut out: W, rows: impl Iterator<Item = Row<'a>>, options: ExportOptions) -> io::Result<()> {
+    let format = options.timestamps.unwrap_or_default();
+    let rows: Vec<Value> = rows
+        .map(|x| {
+            let mut row = json!({
+                "kind": x.kind,
+                "ticker": x.ticker,
+                "date": x.date.to_string(),
+                "time": x.time.map(|t| t.to_string()),
+                "last": x.last,
+                "volume": x.volume,
+                "turnover": x.turnover,
+            });
+
+            if options.timestamps.is_some() {
+                row["timestamp"] = json!(format.format(x.timing.published_at));
+            }
+            if options.timing {
+                row["published_at"] = json!(format.format(x.timing.published_at));
+                row["captured_at"] = json!(x.timing.captured_at.map(|t| format.format(t)));
+                row["lag"] = json!(x.timing.lag().map(|d| d.num_seconds()));
+            }
+
+            row
+        })
+        .collect();
+
+    serde_json::to_writer_pretty(&mut out, &rows)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+    use chrono::TimeDelta;
+    use crate::parser_ibex::IbexParser;
+
+    #[fixture]
+    fn quotes() -> Vec<Quotes> {
+        vec![Quotes {
+            index: IndexQuote::from_entry("IBEX 35®;06/02/2024;17:37:03;10.003,00"),
+            stocks: vec![
+                StockQuote::from_entry("ACS;06/02/2024;Cierre;36,5000;501.552;18.341,96").unwrap(),
+                StockQuote::from_entry("GRIFOLS, CL.A;06/02/2024;17:35:00;9,1000;1.000;9,10").unwrap(),
+            ],
+        }]
+    }
+
+    #[rstest]
+    fn test_write_csv(quotes: Vec<Quotes>) {
+        let mut out = Vec::new();
+        write_csv(&mut out, &quotes).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+kind,ticker,date,time,last,volume,turnover
+index,IBEX 35®,2024-02-06,17:37:03,10003,,
+stock,ACS,2024-02-06,,36.5,501552,18341960
+stock,\"GRIFOLS, CL.A\",2024-02-06,17:35:00,9.1,1000,9100
+");
+    }
+
+    #[rstest]
+    fn test_write_json(quotes: Vec<Quotes>) {
+        let mut out = Vec::new();
+        write_json(&mut out, &quotes).unwrap();
+
+        let rows: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(rows.as_array().unwrap().len(), 3);
+        assert_eq!(rows[0]["volume"], Value::Null);
+        assert_eq!(rows[1], json!({
+            "kind": "stock",
+            "ticker": "ACS",
+            "date": "2024-02-06",
+            "time": null,
+            "last": 36.5,
+            "volume": 501552,
+            "turnover": 18341960.0,
+        }));
+    }
+
+    #[fixture]
+    fn snapshots(quotes: Vec<Quotes>) -> Vec<Snapshot> {
+        // The page shows the market at 17:37:03, and is captured 15 minutes later.
+        let at = NaiveDate::from_ymd_opt(2024, 2, 6).unwrap().and_hms_opt(17, 52, 3).unwrap();
+        let mut snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+        snapshot.index = quotes[0].index.clone();
+        snapshot.stocks = quotes[0].stocks.clone();
+
+        vec![snapshot.clone().with_captured_at(at), snapshot.with_captured_at(at + TimeDelta::minutes(1))]
+    }
+
+    #[rstest]
+    fn test_write_snapshots_csv(snapshots: Vec<Snapshot>) {
+        let mut out = Vec::new();
+        let options = ExportOptions { timing: true, timestamps: None };
+        write_snapshots_csv(&mut out, &snapshots[..1], options).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+kind,ticker,date,time,last,volume,turnover,published_at,captured_at,lag
+index,IBEX 35®,2024-02-06,17:37:03,10003,,,2024-02-06T17:37:03,2024-02-06T17:52:03,900
+stock,ACS,2024-02-06,,36.5,501552,18341960,2024-02-06T17:35:00,2024-02-06T17:52:03,1023
+stock,\"GRIFOLS, CL.A\",2024-02-06,17:35:00,9.1,1000,9100,2024-02-06T17:35:00,2024-02-06T17:52:03,1023
+");
+    }
+
+    #[rstest]
+    fn test_write_snapshots_csv_timestamps(snapshots: Vec<Snapshot>) {
+        let mut out = Vec::new();
+        let options = ExportOptions { timing: false, timestamps: Some(TimeFormat::Rfc3339) };
+        write_snapshots_csv(&mut out, &snapshots[..1], options).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("kind,ticker,date,time,last,volume,turnover,timestamp"));
+        assert_eq!(lines.next(), Some("index,IBEX 35®,2024-02-06,17:37:03,10003,,,2024-02-06T17:37:03+01:00"));
+    }
+
+    #[rstest]
+    fn test_write_snapshots_json(snapshots: Vec<Snapshot>) {
+        let mut out = Vec::new();
+        let options = ExportOptions { timing: true, timestamps: Some(TimeFormat::Utc) };
+        write_snapshots_json(&mut out, &snapshots, options).unwrap();
+
+        let rows: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(rows.as_array().unwrap().len(), 6);
+        assert_eq!(rows[0]["lag"], 900);
+        assert_eq!(rows[4]["timestamp"], "2024-02-06T16:35:00Z");
+        assert_eq!(rows[4]["published_at"], "2024-02-06T16:35:00Z");
+        assert_eq!(rows[4]["captured_at"], "2024-02-06T16:53:03Z");
+        assert_eq!(rows[4]["lag"], 1083);
+
+        let mut out = Vec::new();
+        write_json(&mut out, &[Quotes::from(snapshots[0].clone())]).unwrap();
+        let rows: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(rows[0].get("timestamp"), None);
+        assert_eq!(rows[0].get("lag"), None);
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright 2024 Felipe Torres González
+
+//! Detection of gaps in a sequence of snapshots.
+//!
+//! Data files are usually saved by a scraper every few minutes. When it misses some
+//! intervals, or saves the same page twice, the series of prices has holes that are only
+//! noticed during the analysis. This module checks a sequence of snapshots against the
+//! expected interval between them, and reports:
+//!
+//! - Gaps: consecutive snapshots of a session captured further apart than the interval.
+//! - Duplicates: snapshots captured at the same instant.
+//! - Stale stocks: stocks whose time didn't advance along consecutive snapshots captured
+//!   during the continuous trading, i.e. with no trades, or stuck data.
+
+use std::collections::BTreeMap;
+use chrono::{NaiveDateTime, TimeDelta};
+use crate::calendar::{Calendar, Phase};
+use crate::snapshot::Snapshot;
+use crate::timezone::elapsed;
+
+/// Consecutive snapshots captured further apart than the expected interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    /// Capture time of the snapshot before the gap, or the beginning of the opening auction
+    /// when that snapshot was captured before it.
+    pub from: NaiveDateTime,
+    /// Capture time of the snapshot after the gap, or the end of the closing auction when
+    /// that snapshot was captured after it.
+    pub to: NaiveDateTime,
+    /// Number of snapshots that are missing, given the expected interval.
+    pub missing: usize,
+}
+
+/// Snapshots captured at the same instant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    /// Capture time shared by the snapshots.
+    pub captured_at: NaiveDateTime,
+    /// Sources of the snapshots, in the same order as they were given. Snapshots with no
+    /// source are given as an empty string.
+    pub sources: Vec<String>,
+}
+
+/// A stock whose time didn't advance along consecutive snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stale {
+    /// Name of the stock, as shown in the data files.
+    pub ticker: String,
+    /// Date and time of the quote shown by all the snapshots.
+    pub published_at: NaiveDateTime,
+    /// Capture time of the first snapshot that shows the quote.
+    pub from: NaiveDateTime,
+    /// Capture time of the last snapshot that shows the quote.
+    pub to: NaiveDateTime,
+    /// Number of snapshots that show the quote.
+    pub snapshots: usize,
+}
+
+/// Gaps, duplicates and stale stocks found in a sequence of snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GapReport {
+    /// Number of snapshots with a capture time.
+    pub snapshots: usize,
+    /// Number of snapshots with no capture time, nor a time of the market from which to
+    /// estimate it, which are ignored.
+    pub untimed: usize,
+    /// Gaps sorted by time.
+    pub gaps: Vec<Gap>,
+    /// Duplicates sorted by time.
+    pub duplicates: Vec<Duplicate>,
+    /// Stale stocks sorted by time, and then by name.
+    pub stale: Vec<Stale>,
+}
+
+impl GapReport {
+    /// Whether the sequence of snapshots has no gaps, duplicates or stale stocks.
+    pub fn is_clean(&self) -> bool {
+        self.gaps.is_empty() && self.duplicates.is_empty() && self.stale.is_empty()
+    }
+
+    /// Total number of missing snapshots.
+    pub fn missing(&self) -> usize {
+        self.gaps.iter().map(|x| x.missing).sum()
+    }
+}
+
+/// Check a sequence of snapshots against the expected interval between them.
+///
+/// # Description
+///
+/// Snapshots are sorted by their capture time, so they can be given in any order. When it's
+/// unknown, e.g. for data files read from an archive, it's estimated from the time shown by
+/// the page (see [Snapshot::capture_time]). Only the
+/// snapshots of the same trading day are compared, and the time between them is clipped to
+/// the hours of the session (from the opening auction to the end of the closing auction, see
+/// [Calendar::session]), so the time out of a session is not a gap. Consecutive snapshots are
+/// a gap when snapshots are missing between them given the interval, i.e. when they are
+/// captured further apart than one and a half intervals. The time between snapshots takes
+/// into account the change of the clocks (see [elapsed]).
+///
+/// A stock is stale when consecutive snapshots captured during the continuous trading (see
+/// [Snapshot::phase]) show the same time for it. Duplicates are reported once, and only the
+/// first of them is used for the rest of the checks.
+///
+/// # Panics
+///
+/// When `interval` is not positive.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::calendar::Calendar;
+/// use ibex_parser::gaps::find_gaps;
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use chrono::TimeDelta;
+/// use std::path::Path;
+///
+/// let snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+/// let morning = snapshot.market_at.unwrap().date().and_hms_opt(10, 0, 0).unwrap();
+/// let snapshot = snapshot.with_captured_at(morning);
+/// let later = snapshot.clone().with_captured_at(morning + TimeDelta::minutes(20));
+///
+/// let report = find_gaps(&[snapshot, later], TimeDelta::minutes(5), &Calendar::new());
+/// assert_eq!(report.gaps[0].missing, 3);
+/// ```
+pub fn find_gaps(snapshots: &[Snapshot], interval: TimeDelta, calendar: &Calendar) -> GapReport {
+    assert!(interval > TimeDelta::zero(), "the interval between snapshots must be positive");
+
+    let mut report = GapReport::default();
+    let mut captures: BTreeMap<NaiveDateTime, Vec<&Snapshot>> = BTreeMap::new();
+
+    for snapshot in snapshots {
+        match snapshot.capture_time() {
+            Some(x) => captures.entry(x).or_default().push(snapshot),
+            None => report.untimed += 1,
+        }
+    }
+    report.snapshots = snapshots.len() - report.untimed;
+
+    // Quote shown by the previous snapshot for each stock: time, and the stale run so far.
+    let mut previous: BTreeMap<&str, Stale> = BTreeMap::new();
+    let mut last: Option<NaiveDateTime> = None;
+
+    for (captured_at, group) in captures.iter() {
+        if group.len() > 1 {
+            report.duplicates.push(Duplicate {
+                captured_at: *captured_at,
+                sources: group.iter().map(|x| x.source.clone().unwrap_or_default()).collect(),
+            });
+        }
+
+        if let Some(last) = last.filter(|x| x.date() == captured_at.date()) {
+            report.gaps.extend(find_gap(last, *captured_at, interval, calendar));
+        }
+        last = Some(*captured_at);
+
+        let snapshot = group[0];
+
+        if snapshot.phase(calendar) != Some(Phase::ContinuousTrading) {
+            flush_stale(&mut previous, &mut report.stale);
+            continue;
+        }
+
+        for quote in snapshot.iter() {
+            let published_at = quote.published_at();
+
+            match previous.get_mut(quote.ticker.as_str()) {
+                Some(x) if x.published_at == published_at => {
+                    x.to = *captured_at;
+                    x.snapshots += 1;
+                },
+                _ => {
+                    let run = Stale {
+                        ticker: quote.ticker.clone(),
+                        published_at,
+                        from: *captured_at,
+                        to: *captured_at,
+                        snapshots: 1,
+                    };
+
+                    if let Some(x) = previous.insert(&quote.ticker, run).filter(|x| x.snapshots > 1) {
+                        report.stale.push(x);
+                    }
+                },
+            }
+        }
+    }
+    flush_stale(&mut previous, &mut report.stale);
+
+    report.stale.sort_by(|a, b| (a.from, &a.ticker).cmp(&(b.from, &b.ticker)));
+
+    report
+}
+
+/// Check two consecutive captures of the same day, clipped to the hours of its session.
+fn find_gap(last: NaiveDateTime, next: NaiveDateTime, interval: TimeDelta, calendar: &Calendar) -> Option<Gap> {
+    let session = calendar.session(last.date())?;
+    let from = last.max(session.date.and_time(session.auction));
+    let to = next.min(session.date.and_time(session.end));
+
+    if from >= to {
+        return None;
+    }
+
+    // The ends that are clipped are missing snapshots too.
+    let clipped = usize::from(from != last) + usize::from(to != next);
+    let intervals = elapsed(from, to).num_milliseconds() as f64 / interval.num_milliseconds() as f64;
+    let missing = (intervals.round() as usize + clipped).saturating_sub(1);
+
+    (missing > 0).then_some(Gap { from, to, missing })
+}
+
+/// End the stale runs in progress, keeping those of several snapshots.
+fn flush_stale(previous: &mut BTreeMap<&str, Stale>, stale: &mut Vec<Stale>) {
+    stale.extend(std::mem::take(previous).into_values().filter(|x| x.snapshots > 1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+    use chrono::NaiveDate;
+    use crate::parser_ibex::IbexParser;
+
+    fn at(h: u32, m: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 2, 6).unwrap().and_hms_opt(h, m, 0).unwrap()
+    }
+
+    /// A snapshot captured at some time, which shows the market 15 minutes earlier (the delay
+    /// of the data), and in which every stock was traded 10 minutes before the capture, but
+    /// ACS, which was traded at 09:55.
+    fn snapshot(h: u32, m: u32, source: &str) -> Snapshot {
+        let mut snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+        snapshot.market_at = Some(at(h, m) - TimeDelta::minutes(15));
+        snapshot.source = Some(String::from(source));
+
+        for quote in snapshot.stocks.iter_mut() {
+            let time = if quote.ticker == "ACS" { at(9, 55) } else { at(h, m) - TimeDelta::minutes(10) };
+            quote.time = Some(time.time());
+        }
+
+        snapshot.with_captured_at(at(h, m))
+    }
+
+    #[rstest]
+    fn test_find_gaps() {
+        let snapshots = vec![
+            snapshot(10, 20, "e"),
+            snapshot(10, 0, "a"),
+            snapshot(10, 5, "b"),
+            snapshot(10, 5, "c"),
+            snapshot(10, 7, "d"),
+            snapshot(10, 30, "f"),
+            snapshot(10, 40, "g"),
+            // The closing prices, shown at 17:37:03 and captured 15 minutes later.
+            Snapshot { captured_at: None, ..IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap() },
+        ];
+        let untimed = Snapshot { market_at: None, captured_at: None, ..snapshots[0].clone() };
+
+        let report = find_gaps(&[snapshots, vec![untimed]].concat(), TimeDelta::minutes(5), &Calendar::new());
+
+        assert_eq!((report.snapshots, report.untimed), (8, 1));
+        assert_eq!(report.gaps, vec![
+            Gap { from: at(10, 7), to: at(10, 20), missing: 2 },
+            Gap { from: at(10, 20), to: at(10, 30), missing: 1 },
+            Gap { from: at(10, 30), to: at(10, 40), missing: 1 },
+            // The last snapshot is captured after the end of the session.
+            Gap { from: at(10, 40), to: at(17, 35), missing: 83 },
+        ]);
+        assert_eq!(report.missing(), 87);
+        assert_eq!(report.duplicates, vec![
+            Duplicate { captured_at: at(10, 5), sources: vec![String::from("b"), String::from("c")] },
+        ]);
+        // The last snapshot is captured after the continuous trading.
+        assert_eq!(report.stale, vec![Stale {
+            ticker: String::from("ACS"),
+            published_at: at(9, 55),
+            from: at(10, 0),
+            to: at(10, 40),
+            snapshots: 6,
+        }]);
+        assert!(!report.is_clean());
+    }
+
+    #[rstest]
+    fn test_find_gaps_clean() {
+        let mut snapshots: Vec<Snapshot> = (0..4).map(|x| snapshot(10, x * 5, "")).collect();
+        // A new session.
+        snapshots.push(snapshots[0].clone().with_captured_at(at(10, 0) + TimeDelta::days(1)));
+        for x in snapshots.iter_mut() {
+            x.stocks.retain(|x| x.ticker != "ACS");
+        }
+
+        let report = find_gaps(&snapshots, TimeDelta::minutes(5), &Calendar::new());
+
+        assert_eq!(report, GapReport { snapshots: 5, ..GapReport::default() });
+        assert!(report.is_clean());
+    }
+
+    // The time out of the session is not a gap.
+    #[rstest]
+    #[case(at(8, 0), at(8, 20), vec![])]
+    #[case(at(8, 0), at(8, 50), vec![Gap { from: at(8, 30), to: at(8, 50), missing: 4 }])]
+    #[case(at(17, 20), at(18, 0), vec![Gap { from: at(17, 20), to: at(17, 35), missing: 3 }])]
+    #[case(at(18, 0), at(19, 0), vec![])]
+    #[case(at(10, 0) + TimeDelta::days(4), at(11, 0) + TimeDelta::days(4), vec![])]
+    fn test_find_gaps_session(#[case] from: NaiveDateTime, #[case] to: NaiveDateTime, #[case] expected: Vec<Gap>) {
+        let snapshots = [snapshot(10, 0, "").with_captured_at(from), snapshot(10, 0, "").with_captured_at(to)];
+
+        assert_eq!(find_gaps(&snapshots, TimeDelta::minutes(5), &Calendar::new()).gaps, expected);
+    }
+
+    #[rstest]
+    #[should_panic(expected = "must be positive")]
+    fn test_find_gaps_interval() {
+        find_gaps(&[], TimeDelta::zero(), &Calendar::new());
+    }
+}
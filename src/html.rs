@@ -0,0 +1,160 @@
+// Copyright 2024 Felipe Torres González
+
+//! Extraction of the data tables from BME's web page saved as HTML.
+//!
+//! Saving the HTML markup of the page is more reliable than copying its content as text,
+//! as the result doesn't depend on the browser. This module locates the tables of the page
+//! so their rows can be handled by the parser in the same way as the rows of a text file.
+
+use scraper::{ElementRef, Html, Selector};
+
+/// A table found in the HTML markup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    /// Column names, as found in the header of the table.
+    pub header: Vec<String>,
+    /// The content of the cells of each row of the table body.
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Check whether some content looks like HTML markup rather than text.
+pub fn is_html(content: &str) -> bool {
+    content.trim_start().starts_with('<')
+}
+
+/// Extract the tables found in an HTML document.
+///
+/// # Description
+///
+/// The tables are searched inside the element `div#root`, which contains the data of the
+/// page. If that element is not found, the entire document is used.
+///
+/// The header of a table is taken from its cells `th`, and the rest of rows are taken
+/// from the cells `td`. The text of the cells is trimmed and any sequence of white
+/// spaces is replaced by a single space, so the cells have the same content as the
+/// columns of a text file.
+///
+/// ## Return
+///
+/// A vector with the tables in the same order as they are found in the document.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::html::tables;
+///
+/// let html = r#"<div id="root"><table>
+///     <thead><tr><th>Nombre</th><th>Último</th></tr></thead>
+///     <tbody><tr><td><a href="/ACS">ACS</a></td><td>36,5000</td></tr></tbody>
+/// </table></div>"#;
+///
+/// let tables = tables(html);
+/// assert_eq!(tables[0].header, vec!["Nombre", "Último"]);
+/// assert_eq!(tables[0].rows, vec![vec!["ACS", "36,5000"]]);
+/// ```
+pub fn tables(html: &str) -> Vec<Table> {
+    let document = Html::parse_document(html);
+    let root_sel = Selector::parse("div#root").unwrap();
+    let table_sel = Selector::parse("table").unwrap();
+    let row_sel = Selector::parse("tr").unwrap();
+    let th_sel = Selector::parse("th").unwrap();
+    let td_sel = Selector::parse("td").unwrap();
+
+    let root = document.select(&root_sel).next().unwrap_or(document.root_element());
+    let mut result = Vec::new();
+
+    for table in root.select(&table_sel) {
+        let mut header = Vec::new();
+        let mut rows = Vec::new();
+
+        for row in table.select(&row_sel) {
+            let head_cells: Vec<String> = row.select(&th_sel).map(cell_text).collect();
+
+            if header.is_empty() && !head_cells.is_empty() {
+                header = head_cells;
+                continue;
+            }
+
+            let cells: Vec<String> = row.select(&td_sel).map(cell_text).collect();
+
+            if !cells.is_empty() {
+                rows.push(cells);
+            }
+        }
+
+        result.push(Table { header, rows });
+    }
+
+    result
+}
+
+/// Find the first table whose header starts with a given column name.
+pub fn find_table<'a>(tables: &'a [Table], first_column: &str) -> Option<&'a Table> {
+    tables.iter().find(|x| x.header.first().is_some_and(|c| c == first_column))
+}
+
+/// Extract the text of an HTML document.
+///
+/// Each piece of text of the document results in a line, so the result is alike to the
+/// content of a text file saved from the same page. Empty lines are removed.
+pub fn text_lines(html: &str) -> Vec<String> {
+    Html::parse_document(html)
+        .root_element()
+        .text()
+        .map(|x| x.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn cell_text(cell: ElementRef) -> String {
+    cell.text().collect::<String>().split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use std::fs::read_to_string;
+
+    #[fixture]
+    fn html_data() -> String {
+        read_to_string("./tests/data/data_ibex.html").unwrap()
+    }
+
+    #[rstest]
+    fn test_tables(html_data: String) {
+        let tables = tables(&html_data);
+
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].header[0], "Índice");
+        assert_eq!(tables[0].rows.len(), 1);
+        assert_eq!(tables[1].header.len(), 9);
+        assert_eq!(tables[1].rows.len(), 35);
+        assert_eq!(tables[1].rows[7][0], "B.SANTANDER");
+    }
+
+    #[rstest]
+    fn test_find_table(html_data: String) {
+        let tables = tables(&html_data);
+
+        assert_eq!(find_table(&tables, "Nombre"), Some(&tables[1]));
+        assert_eq!(find_table(&tables, "Valor"), None);
+    }
+
+    #[rstest]
+    fn test_text_lines(html_data: String) {
+        let lines = text_lines(&html_data);
+
+        assert!(lines.contains(&String::from("GRÁFICO IBEX 35")));
+        assert!(lines.iter().any(|x| x.starts_with("Información diferida")));
+    }
+
+    #[rstest]
+    #[case("  <!DOCTYPE html>", true)]
+    #[case("<div id=\"root\">", true)]
+    #[case("Mercado\nÍndice", false)]
+    fn test_is_html(#[case] content: &str, #[case] expected: bool) {
+        assert_eq!(is_html(content), expected);
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright 2024 Felipe Torres González
+
+//! Description of the layout of the pages published by BME.
+//!
+//! BME publishes the prices of several indices, and of the entire Mercado Continuo, using
+//! pages that share the same table layout. A [Layout] describes where the data is found in
+//! one of those pages, so [IbexParser][crate::parser_ibex::IbexParser] can parse all of them.
+
+use std::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::record::RecordKind;
+
+/// Name of the built-in layout for the IBEX 35 page.
+pub const IBEX35: &str = "ibex35";
+/// Name of the built-in layout for the IBEX MEDIUM CAP page.
+pub const IBEX_MEDIUM_CAP: &str = "ibex-medium-cap";
+/// Name of the built-in layout for the IBEX SMALL CAP page.
+pub const IBEX_SMALL_CAP: &str = "ibex-small-cap";
+/// Name of the built-in layout for the sector pages of the IBEX 35 (e.g. IBEX 35 BANCOS).
+pub const IBEX35_SECTOR: &str = "ibex35-sector";
+/// Name of the built-in layout for the Mercado Continuo page.
+pub const MERCADO_CONTINUO: &str = "mercado-continuo";
+
+/// Columns parsed from the row of the index, in output order.
+const INDEX_COLUMNS: [(&str, FieldKind); 4] = [
+    ("Índice", FieldKind::Text),
+    ("Fecha", FieldKind::Date),
+    ("Hora", FieldKind::Time),
+    ("Último", FieldKind::Decimal),
+];
+/// Columns parsed from the rows of the stocks, in output order.
+const STOCK_COLUMNS: [(&str, FieldKind); 6] = [
+    ("Nombre", FieldKind::Text),
+    ("Fecha", FieldKind::Date),
+    ("Hora", FieldKind::Time),
+    ("Último", FieldKind::Decimal),
+    ("Volumen", FieldKind::Integer),
+    ("Efectivo (miles €)", FieldKind::Decimal),
+];
+
+/// How a column of a table is identified.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum ColumnRef {
+    /// Position of the column in the table, starting from 0.
+    Index(usize),
+    /// Name of the column, as found in the header of the table.
+    Name(String),
+}
+
+impl fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnRef::Index(x) => write!(f, "#{x}"),
+            ColumnRef::Name(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+/// The type of the values of a column.
+///
+/// Values are checked against their type when parsing a file, and a file that contains
+/// values of the wrong type is not valid. Numbers use the Spanish format, i.e. `.` for
+/// thousands and `,` for decimals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum FieldKind {
+    /// Any value.
+    #[default]
+    Text,
+    /// An integer number, e.g. `1.945.142`.
+    Integer,
+    /// A decimal number, e.g. `10.003,00`.
+    Decimal,
+    /// A decimal number followed by `%`, e.g. `-0,98%`.
+    Percent,
+    /// A date with the format `DD/MM/YYYY`.
+    Date,
+    /// A time with the format `HH:MM:SS`, or `Cierre` once the session is closed.
+    Time,
+}
+
+impl FieldKind {
+    /// Check whether a value has the format of this type.
+    ///
+    /// # Example of use
+    ///
+    /// ```rust
+    /// use ibex_parser::layout::FieldKind;
+    ///
+    /// assert!(FieldKind::Decimal.matches("-10.003,00"));
+    /// assert!(FieldKind::Time.matches("Cierre"));
+    /// assert!(!FieldKind::Date.matches("06 febrero 2024"));
+    /// ```
+    pub fn matches(&self, value: &str) -> bool {
+        let is_number = |x: &str, decimals: bool| {
+            let x = x.strip_prefix('-').unwrap_or(x);
+            let (int, dec) = match x.split_once(',') {
+                Some((int, dec)) if decimals => (int, dec),
+                Some(_) => return false,
+                None => (x, "0"),
+            };
+
+            !int.is_empty() && !int.starts_with('.') && !int.ends_with('.')
+                && int.chars().all(|c| c.is_ascii_digit() || c == '.')
+                && !dec.is_empty() && dec.chars().all(|c| c.is_ascii_digit())
+        };
+        let is_split = |x: &str, sep: char, lengths: &[usize]| {
+            let parts: Vec<&str> = x.split(sep).collect();
+            parts.len() == lengths.len() && parts.iter().zip(lengths).all(|(p, l)| {
+                p.len() == *l && p.chars().all(|c| c.is_ascii_digit())
+            })
+        };
+
+        match self {
+            FieldKind::Text => true,
+            FieldKind::Integer => is_number(value, false),
+            FieldKind::Decimal => is_number(value, true),
+            FieldKind::Percent => value.strip_suffix('%').is_some_and(|x| is_number(x, true)),
+            FieldKind::Date => is_split(value, '/', &[2, 2, 4]),
+            FieldKind::Time => value == "Cierre" || is_split(value, ':', &[2, 2, 2]),
+        }
+    }
+}
+
+/// A column of a table that is parsed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Column {
+    /// How the column is found in the table.
+    pub source: ColumnRef,
+    /// The type of the values of the column.
+    pub kind: FieldKind,
+}
+
+impl Column {
+    /// Build a column identified by its name.
+    pub fn named(name: &str, kind: FieldKind) -> Column {
+        Column { source: ColumnRef::Name(String::from(name)), kind }
+    }
+
+    /// Find the position of the column in a table.
+    ///
+    /// Columns identified by their name are searched in `header`. `None` is returned when
+    /// the column is not found, or when there's no header to search in.
+    pub fn resolve<S: AsRef<str>>(&self, header: Option<&[S]>) -> Option<usize> {
+        match &self.source {
+            ColumnRef::Index(x) => Some(*x),
+            ColumnRef::Name(name) => header?.iter().position(|x| x.as_ref() == name),
+        }
+    }
+}
+
+impl From<usize> for Column {
+    /// Build a column of text identified by its position.
+    fn from(index: usize) -> Column {
+        Column { source: ColumnRef::Index(index), kind: FieldKind::Text }
+    }
+}
+
+/// How the rows that contain data are located in a text file.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum Bounds {
+    /// Rows are found after the header of each table, and up to the first line that doesn't
+    /// have the same number of columns as the header (usually an empty line).
+    Markers,
+    /// Rows are found by their position in the file.
+    ///
+    /// The header of each table is expected in the line just before its first row, i.e.
+    /// `index_line - 1` for the index and `skip_beg - 1` for the stocks.
+    ///
+    /// - `skip_beg`: number of lines at the beginning of the file that are ignored.
+    /// - `index_line`: position of the line that contains the data of the index. It is
+    ///   parsed even when it is inside the ignored lines.
+    /// - `skip_end`: number of lines at the end of the file that are ignored.
+    Fixed { skip_beg: usize, index_line: usize, skip_end: usize },
+}
+
+/// The layout of a page that contains stock prices.
+///
+/// # Description
+///
+/// All the pages are composed of an optional table with the data of the index, and a table
+/// with the data of the stocks. Each table is identified by the name of its first column,
+/// which is written in the line just before the first row of data (text files) or in the
+/// header of the table (HTML files). Hence, the number of stocks in the page doesn't
+/// matter: it might be a sector page with a couple of stocks, or the Mercado Continuo page
+/// with hundreds of them.
+///
+/// Use [Layout::builtin] to get the layout of one of the pages published by BME, or load a
+/// layout from a profile file using [profile::load][crate::profile::load].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Layout {
+    /// A short name that identifies the layout.
+    pub name: String,
+    /// The title of the page, or its first words, as shown in the data file (e.g. "IBEX 35").
+    pub title: String,
+    /// Whether `title` is only the first words of the title of the page, e.g. "IBEX 35" for
+    /// the sector pages of the IBEX 35, titled "IBEX 35 BANCOS", "IBEX 35 ENERGÍA", etc.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub title_prefix: bool,
+    /// The name of the first column of the table of the index. `None` when the page has no
+    /// table for an index.
+    pub index_header: Option<String>,
+    /// The name of the first column of the table of the stocks.
+    pub stock_header: String,
+    /// The beginning of the first line of the notes found after the tables.
+    pub footer: String,
+    /// Columns that are parsed from the row of the index, in output order.
+    pub index_columns: Vec<Column>,
+    /// Columns that are parsed from the rows of the stocks, in output order.
+    pub stock_columns: Vec<Column>,
+    /// How the rows are located in text files.
+    pub bounds: Bounds,
+}
+
+impl Layout {
+    /// Build the layout of the page of an index published by BME.
+    ///
+    /// All the index pages share the same layout, only their title changes.
+    pub fn index_page(name: &str, title: &str) -> Layout {
+        Layout {
+            name: String::from(name),
+            title: String::from(title),
+            title_prefix: false,
+            index_header: Some(String::from("Índice")),
+            stock_header: String::from("Nombre"),
+            footer: String::from("Información diferida"),
+            index_columns: INDEX_COLUMNS.iter().map(|(x, k)| Column::named(x, *k)).collect(),
+            stock_columns: STOCK_COLUMNS.iter().map(|(x, k)| Column::named(x, *k)).collect(),
+            bounds: Bounds::Markers,
+        }
+    }
+
+    /// The layout of the [IBEX 35 page][ibex35_data].
+    ///
+    /// [ibex35_data]: https://www.bolsasymercados.es/bme-exchange/es/Mercados-y-Cotizaciones/Acciones/Mercado-Continuo/Precios/ibex-35-ES0SI0000005
+    pub fn ibex35() -> Layout {
+        Layout::index_page(IBEX35, "IBEX 35")
+    }
+
+    /// The layout of the Mercado Continuo page, which lists all the stocks of the market
+    /// and has no table for an index.
+    pub fn mercado_continuo() -> Layout {
+        Layout {
+            index_header: None,
+            ..Layout::index_page(MERCADO_CONTINUO, "Mercado Continuo")
+        }
+    }
+
+    /// Get a built-in layout using its name.
+    ///
+    /// See [Layout::builtin_names] for the available names. `None` is returned when the
+    /// name doesn't match a built-in layout.
+    ///
+    /// # Example of use
+    ///
+    /// ```rust
+    /// use ibex_parser::layout::Layout;
+    /// use ibex_parser::parser_ibex::IbexParser;
+    ///
+    /// let layout = Layout::builtin("ibex-medium-cap").unwrap();
+    /// let parser = IbexParser::with_layout(layout);
+    /// ```
+    pub fn builtin(name: &str) -> Option<Layout> {
+        match name {
+            IBEX35 => Some(Layout::ibex35()),
+            IBEX_MEDIUM_CAP => Some(Layout::index_page(IBEX_MEDIUM_CAP, "IBEX MEDIUM CAP")),
+            IBEX_SMALL_CAP => Some(Layout::index_page(IBEX_SMALL_CAP, "IBEX SMALL CAP")),
+            IBEX35_SECTOR => Some(Layout {
+                title_prefix: true,
+                ..Layout::index_page(IBEX35_SECTOR, "IBEX 35")
+            }),
+            MERCADO_CONTINUO => Some(Layout::mercado_continuo()),
+            _ => None,
+        }
+    }
+
+    /// Check whether a line of a data file is the title of the page.
+    ///
+    /// Both the line and the title are trimmed before comparing them. When the title is only
+    /// the first words of the title of the page (see `title_prefix`), the line must have
+    /// more words after it.
+    pub fn matches_title(&self, line: &str) -> bool {
+        match line.trim().strip_prefix(self.title.trim()) {
+            Some(rest) if self.title_prefix => rest.starts_with(char::is_whitespace),
+            Some(rest) => rest.is_empty(),
+            None => false,
+        }
+    }
+
+    /// Position of the date among the values of the records of a kind, i.e. the first of
+    /// the parsed columns whose type is [FieldKind::Date].
+    pub fn date_column(&self, kind: RecordKind) -> Option<usize> {
+        let columns = match kind {
+            RecordKind::Index => &self.index_columns,
+            RecordKind::Stock => &self.stock_columns,
+        };
+
+        columns.iter().position(|x| x.kind == FieldKind::Date)
+    }
+
+    /// Names of the built-in layouts.
+    pub fn builtin_names() -> [&'static str; 5] {
+        [IBEX35, IBEX_MEDIUM_CAP, IBEX_SMALL_CAP, IBEX35_SECTOR, MERCADO_CONTINUO]
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::ibex35()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+
+    #[rstest]
+    fn test_layout_builtin() {
+        for name in Layout::builtin_names() {
+            assert_eq!(Layout::builtin(name).unwrap().name, name);
+        }
+
+        assert_eq!(Layout::builtin("ibex-top-dividendo"), None);
+    }
+
+    #[rstest]
+    #[case("ibex35", "IBEX 35", true)]
+    #[case("ibex35", " IBEX 35\r", true)]
+    #[case("ibex35", "IBEX 35 BANCOS", false)]
+    #[case("ibex35", "IBEX 35®", false)]
+    #[case("ibex35-sector", "IBEX 35 BANCOS", true)]
+    #[case("ibex35-sector", "IBEX 35", false)]
+    #[case("ibex35-sector", "IBEX 35 ", false)]
+    #[case("ibex35-sector", "GRÁFICO IBEX 35 BANCOS", false)]
+    fn test_layout_matches_title(#[case] name: &str, #[case] line: &str, #[case] expected: bool) {
+        assert_eq!(Layout::builtin(name).unwrap().matches_title(line), expected);
+    }
+
+    #[rstest]
+    fn test_layout_mercado_continuo() {
+        let layout = Layout::mercado_continuo();
+
+        assert_eq!(layout.index_header, None);
+        assert_eq!(layout.stock_columns, Layout::ibex35().stock_columns);
+    }
+
+    #[rstest]
+    fn test_layout_date_column() {
+        let layout = Layout {
+            index_columns: vec![Column::named("Índice", FieldKind::Text)],
+            stock_columns: vec![
+                Column::named("Nombre", FieldKind::Text),
+                Column::named("Último", FieldKind::Decimal),
+                Column::named("Fecha", FieldKind::Date),
+            ],
+            ..Layout::ibex35()
+        };
+
+        assert_eq!(Layout::ibex35().date_column(RecordKind::Index), Some(1));
+        assert_eq!(layout.date_column(RecordKind::Stock), Some(2));
+        assert_eq!(layout.date_column(RecordKind::Index), None);
+    }
+
+    #[rstest]
+    #[case(FieldKind::Integer, "1.945.142", true)]
+    #[case(FieldKind::Integer, "62522", true)]
+    #[case(FieldKind::Integer, "1.945,14", false)]
+    #[case(FieldKind::Decimal, "10.003,00", true)]
+    #[case(FieldKind::Decimal, "-0,5", true)]
+    #[case(FieldKind::Decimal, "114", true)]
+    #[case(FieldKind::Decimal, "12,", false)]
+    #[case(FieldKind::Decimal, "-", false)]
+    #[case(FieldKind::Percent, "-0,98%", true)]
+    #[case(FieldKind::Percent, "0,98", false)]
+    #[case(FieldKind::Date, "06/02/2024", true)]
+    #[case(FieldKind::Date, "6/2/2024", false)]
+    #[case(FieldKind::Time, "17:37:03", true)]
+    #[case(FieldKind::Time, "Cierre", true)]
+    #[case(FieldKind::Time, "17:37", false)]
+    #[case(FieldKind::Text, "", true)]
+    fn test_fieldkind_matches(#[case] kind: FieldKind, #[case] value: &str, #[case] expected: bool) {
+        assert_eq!(kind.matches(value), expected);
+    }
+
+    #[rstest]
+    fn test_column_resolve() {
+        let header = ["Nombre", "Último", "% Dif."];
+
+        assert_eq!(Column::named("Último", FieldKind::Decimal).resolve(Some(&header[..])), Some(1));
+        assert_eq!(Column::named("Hora", FieldKind::Time).resolve(Some(&header[..])), None);
+        assert_eq!(Column::named("Último", FieldKind::Decimal).resolve::<&str>(None), None);
+        assert_eq!(Column::from(7).resolve::<&str>(None), Some(7));
+    }
+
+    #[cfg(feature = "serde")]
+    #[rstest]
+    fn test_layout_serde() {
+        use serde_json::json;
+
+        let layout = Layout {
+            index_columns: vec![Column::named("Índice", FieldKind::Text), Column::from(6)],
+            bounds: Bounds::Fixed { skip_beg: 11, index_line: 6, skip_end: 8 },
+            ..Layout::ibex35()
+        };
+        let value = serde_json::to_value(&layout).unwrap();
+
+        assert_eq!(value["name"], "ibex35");
+        assert_eq!(value["index_header"], "Índice");
+        assert_eq!(value["stock_header"], "Nombre");
+        assert_eq!(value["index_columns"], json!([
+            {"source": {"name": "Índice"}, "kind": "text"},
+            {"source": {"index": 6}, "kind": "text"},
+        ]));
+        assert_eq!(value["stock_columns"][5], json!({"source": {"name": "Efectivo (miles €)"}, "kind": "decimal"}));
+        assert_eq!(value["bounds"], json!({"fixed": {"skip_beg": 11, "index_line": 6, "skip_end": 8}}));
+        assert_eq!(serde_json::to_value(Bounds::Markers).unwrap(), "markers");
+        assert_eq!(serde_json::from_value::<Layout>(value).unwrap(), layout);
+    }
+}
@@ -1,11 +1,37 @@
 // Copyright 2024 Felipe Torres González
 
+pub mod archive;
+pub mod bars;
+pub mod batch;
+pub mod calendar;
+pub mod detect;
+pub mod diagnostics;
+pub mod export;
+pub mod gaps;
+pub mod html;
+pub mod layout;
+pub mod manifest;
+pub mod normalize;
 pub mod parser_ibex;
+pub mod partition;
+pub mod profile;
+pub mod quote;
+pub mod record;
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "sqlite")]
+pub mod storage;
+pub mod timezone;
+pub mod watch;
+#[cfg(test)]
+mod testing;
 
 use std::path::{
     Path,
     PathBuf
 };
+use archive::ArchiveKind;
+use layout::Layout;
 
 /// Discover files that contain raw data for the stock prices of the Ibex 35.
 ///
@@ -18,6 +44,12 @@ use std::path::{
 /// - First, a string that indicates the extension that the files must have.
 /// - Second, a string that indicates the beginning of the file names.
 ///
+/// Archives (`.zip`, `.tar`, `.tar.gz` and `.tgz`) are also included in the result when their
+/// names begin with the same string as the data files, e.g. `data_ibex_2024-02-06.zip`, as
+/// they might contain data files. Use [archive::read_entries] to get the data files stored in
+/// them. Single compressed files (`.gz`) are only included when the file they contain
+/// satisfies the filters, e.g. `data_ibex(2).csv.gz`.
+///
 /// ## Arguments
 ///
 /// - `path` an instance of the struct `Path` that points to the directory that needs to be
@@ -73,45 +105,161 @@ use std::path::{
 ///
 /// As those files use the default filter and extension, we have no need to specify those
 /// when calling the function `discover`.
+#[allow(clippy::doc_overindented_list_items)]
 pub fn discover(path: &Path, filter: Option<&str>, format: Option<&str>) -> Vec<String> {
-    let filter = if let Some(x) = filter {
-        String::from(x)
-    } else {
-        String::from("data_ibex")
-    };
+    let mut files: Vec<String> = Vec::new();
+
+    for entry in path.read_dir().expect("Can't read the directory").flatten() {
+        if entry.metadata().unwrap().is_file() {
+            // An owned version of a Path.
+            let cur_file: PathBuf = entry.path();
+
+            if is_data_file(&cur_file, filter, format) {
+                files.push(String::from(cur_file.file_name().unwrap().to_str().unwrap()));
+            }
+        }
+    }
+
+    files
+}
 
-    let file_format = if let Some(x) = format {
-        String::from(x)
-    } else {
-        String::from("csv")
+/// Check whether the name of a file matches the data files searched by [discover].
+///
+/// The arguments `filter` and `format` have the same meaning, and the same default values,
+/// as in [discover]. Archives are accepted when their names begin with `filter`, whatever
+/// `format` is, as it applies to the data files stored in them.
+pub fn is_data_file(path: &Path, filter: Option<&str>, format: Option<&str>) -> bool {
+    let filter = filter.unwrap_or("data_ibex");
+    let format = format.unwrap_or("csv");
+    let Some(name) = path.file_name().and_then(|x| x.to_str()) else {
+        return false;
     };
 
+    match ArchiveKind::from_path(path) {
+        // The name of a compressed file is the name of the data file plus ".gz".
+        Some(ArchiveKind::Gz) => archive::matches(
+            path.file_stem().unwrap().to_str().unwrap(), filter, format
+        ),
+        Some(_) => name.starts_with(filter),
+        None => archive::matches(name, filter, format),
+    }
+}
+
+/// Discover the data files of a directory using their content rather than their names.
+///
+/// # Description
+///
+/// This function scans a directory non-recursively, like [discover], but every file is
+/// considered whatever its name: the first lines of each file are inspected (see
+/// [detect::sniff_file]) to keep only the files that are data files of one of the given
+/// layouts. Hence, data files might be named in any way, e.g. `ibex-2024-02-06-1737.txt`.
+///
+/// Archives are always included in the result, as in [discover]. Use [archive::read_all] to get
+/// all the files stored in them.
+///
+/// ## Arguments
+///
+/// - `path` an instance of the struct `Path` that points to the directory that needs to be
+///   analysed.
+/// - `format` a wrapped string slice that indicates the extension of the files that should be
+///   inspected. If `None` is passed, files with any extension (or none) are inspected.
+/// - `layouts` the layouts of the data files, e.g. the candidates of a
+///   [Detector][detect::Detector].
+///
+/// ## Return
+///
+/// A vector of strings is returned containing the entire file names of the data files found.
+/// Files that can't be read, or whose names are not valid UTF-8, are not included.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::discover_by_content;
+/// use ibex_parser::layout::Layout;
+/// use std::path::Path;
+///
+/// let layouts = [Layout::ibex35()];
+/// let files = discover_by_content(Path::new("./tests/data"), None, &layouts);
+/// assert!(files.contains(&String::from("data_ibex.html")));
+/// ```
+pub fn discover_by_content(path: &Path, format: Option<&str>, layouts: &[Layout]) -> Vec<String> {
     let mut files: Vec<String> = Vec::new();
 
-    for entry in path.read_dir().expect("Can't read the directory") {
-        if let Ok(entry) = entry {
-            if entry.metadata().unwrap().is_file() {
-                // An owned version of a Path.
-                let cur_file: PathBuf = entry.path();
-
-                // Avoid panicking when a file without format is found.
-                let extension = if let Some(x) = cur_file.extension() {
-                    x.to_str().unwrap()
-                } else {
-                    "_"
-                };
-
-                if extension == file_format &&
-                   filter == cur_file.file_stem().unwrap().to_str().unwrap()[..filter.len()] {
-                    files.push(
-                        String::from(cur_file.file_name().unwrap().to_str().unwrap())
-                    );
-                } else {
-                    continue;
-                }
-            }
+    for entry in path.read_dir().expect("Can't read the directory").flatten() {
+        // Entries whose metadata can't be read, or whose names are not valid UTF-8, are skipped.
+        if !entry.metadata().is_ok_and(|x| x.is_file()) {
+            continue;
+        }
+
+        let cur_file: PathBuf = entry.path();
+        let Some(name) = cur_file.file_name().and_then(|x| x.to_str()) else {
+            continue;
+        };
+        let extension = cur_file.extension().and_then(|x| x.to_str());
+
+        let found = if ArchiveKind::from_path(&cur_file).is_some() {
+            true
+        } else if format.is_some_and(|x| extension != Some(x)) {
+            false
+        } else {
+            detect::sniff_file(&cur_file, layouts).is_ok_and(|x| x.is_some())
+        };
+
+        if found {
+            files.push(String::from(name));
         }
     }
 
     files
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use crate::testing::*;
+
+    // Files are found whatever their names, as long as they contain stock data.
+    #[rstest]
+    #[case(None, vec!["data_ibex35_bancos.csv", "data_ibex_medium_cap.csv", "data_mercado_continuo.csv"])]
+    #[case(Some("txt"), vec![])]
+    fn test_discover_by_content(#[case] format: Option<&str>, #[case] expected: Vec<&str>) {
+        let layouts: Vec<Layout> = Layout::builtin_names().into_iter().filter_map(Layout::builtin).collect();
+        let mut files = discover_by_content(Path::new("./tests/data/layouts"), format, &layouts);
+        files.sort();
+
+        assert_eq!(files, expected);
+    }
+
+    // Files whose names are not valid UTF-8 are skipped, rather than making the search panic.
+    #[cfg(unix)]
+    #[rstest]
+    fn test_discover_by_content_wrong_name(tmp: TempDir) {
+        use std::os::unix::ffi::OsStrExt;
+
+        let data = std::fs::read("./tests/data/data_ibex.csv").unwrap();
+        std::fs::write(tmp.0.join(std::ffi::OsStr::from_bytes(b"data_\xff.csv")), &data).unwrap();
+        std::fs::write(tmp.0.join("data.csv"), &data).unwrap();
+
+        assert_eq!(discover_by_content(&tmp.0, None, &[Layout::ibex35()]), vec!["data.csv"]);
+    }
+
+    // Archives are filtered by name, whatever the extension of the data files.
+    #[rstest]
+    #[case("data_ibex(1).csv", None, None, true)]
+    #[case("data_ibex(1).csv", None, Some("html"), false)]
+    #[case("data_ibex(2).csv.gz", None, None, true)]
+    #[case("data_ibex_2024-02-06.zip", None, None, true)]
+    #[case("data_ibex_2024-02-06.tar.gz", None, Some("html"), true)]
+    #[case("snapshots.zip", None, None, false)]
+    #[case("snapshots.zip", Some("snap"), None, true)]
+    fn test_is_data_file(
+        #[case] name: &str,
+        #[case] filter: Option<&str>,
+        #[case] format: Option<&str>,
+        #[case] expected: bool
+    ) {
+        assert_eq!(is_data_file(Path::new(name), filter, format), expected);
+    }
+}
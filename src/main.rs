@@ -1,12 +1,39 @@
 // Copyright 2024 Felipe Torres González
 
-use ibex_parser::discover;
-use ibex_parser::parser_ibex::IbexParser;
-use std::path::Path;
-use clap::Parser;
+use ibex_parser::{discover, discover_by_content, is_data_file};
+use ibex_parser::archive::{read_all, ArchiveEntry, ArchiveKind};
+use ibex_parser::bars::{aggregate, aggregate_session, parse_interval};
+use ibex_parser::calendar::{Calendar, Phase};
+use ibex_parser::batch::Batch;
+use ibex_parser::detect::{sniff, sniff_file, Detector};
+use ibex_parser::diagnostics::{Diagnostics, SkipReason};
+use ibex_parser::gaps::find_gaps;
+use ibex_parser::export::{write_snapshots_csv, write_snapshots_json, ExportOptions};
+use ibex_parser::layout::Layout;
+use ibex_parser::normalize::decode;
+use ibex_parser::parser_ibex::{IbexParser, Mode, ParseError};
+use ibex_parser::partition::{PartitionBy, PartitionWriter};
+use ibex_parser::profile;
+use ibex_parser::quote::{format_decimal, format_integer, Quotes};
+use ibex_parser::record::Records;
+use ibex_parser::snapshot::Snapshot;
+use ibex_parser::stats::{breadth, summarize, Mover};
+use ibex_parser::timezone::{local_time, TimeFormat};
+use ibex_parser::watch::DirWatcher;
+use ibex_parser::manifest::{Change, Manifest};
+#[cfg(feature = "sqlite")]
+use ibex_parser::storage::Storage;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use chrono::TimeDelta;
+use sha2::{Digest, Sha256};
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use clap::builder::PossibleValuesParser;
 
-// The minium size of a text file that might contain stock data. Files with less than this size are omitted.
-const MIN_BYTES_X_FILE: u64 = 560;
+// Value of the option `--layout` that enables the detection of the layout of each file.
+const AUTO_LAYOUT: &str = "auto";
 
 #[derive(Parser, Debug)]
 #[command(name = "IbexParser")]
@@ -19,42 +46,1067 @@ or graph tool.
 Raw text files shall keep the same data organization as BME's web does. For example, select all the content
 of the page and paste it into a text file. That file is ready to be used by this parser.
 "#)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Directory to search for text data files.
-    path: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Directory to search for text data files, or a single data file or archive.
+    #[arg(required = true)]
+    path: Option<String>,
     /// Company to filter the results.
     filter: Option<String>,
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+    #[command(flatten)]
+    layout: LayoutArgs,
+    /// Number of data files parsed at the same time [default: number of CPUs].
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Print a report of the processed files to the standard error at the end of the run.
+    #[arg(long, value_enum)]
+    report: Option<ReportFormat>,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Parse the data files and print their data (default when no subcommand is given).
+    Parse {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Company to filter the results.
+        filter: Option<String>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Check whether the data files can be parsed, and report the layout of each one.
+    Validate {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Aggregate the prices of the stocks into bars (OHLC) of a fixed interval.
+    Bars {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Length of the bars, e.g. 30s, 5m or 1h.
+        #[arg(short, long, default_value = "5m", value_parser = interval)]
+        interval: TimeDelta,
+        /// Only aggregate the prices of this stock.
+        #[arg(short, long)]
+        ticker: Option<String>,
+        /// Skip the prices published out of a session of the market: on weekends and market
+        /// holidays, or out of the hours of the session.
+        #[arg(long)]
+        session_only: bool,
+    },
+    /// Print summary statistics of the prices of each stock.
+    Stats {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Print the breadth of the market in each data file: advancers, decliners, stocks above
+    /// their open, largest changes and share of the turnover.
+    Breadth {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Number of stocks listed as the largest rises, falls and shares of the turnover.
+        #[arg(long, default_value_t = 3)]
+        top: usize,
+    },
+    /// Report the gaps, the duplicated capture times and the stale stocks of a sequence of
+    /// data files saved at a regular interval.
+    Gaps {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Expected time between data files, e.g. 30s, 5m or 1h.
+        #[arg(short, long, value_parser = interval)]
+        interval: TimeDelta,
+    },
+    /// Export the quotes to a standard format.
+    Export {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Format of the exported data.
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// File to which the data is written. The data is printed when not given.
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Add the time at which each quote was published, the time at which its page was
+        /// captured, and the lag between both in seconds.
+        #[arg(long)]
+        timing: bool,
+        /// Add the date and time of each quote as a timestamp. The times added by "--timing"
+        /// are written with the same format.
+        #[arg(long, value_enum)]
+        timestamps: Option<TimeFormat>,
+    },
+    /// Parse the data files and store the quotes in a SQLite database.
+    #[cfg(feature = "sqlite")]
+    Ingest {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Database file. It is created if it doesn't exist.
+        #[arg(short, long, default_value = "ibex_parser.db")]
+        db: String,
+        /// Manifest file (JSON) that records the processed files. When given, only the files
+        /// that are new or changed since the last run are parsed.
+        #[arg(short, long)]
+        manifest: Option<String>,
+    },
+    /// Watch a directory, and parse the new data files as soon as they are fully written.
+    Watch {
+        /// Directory to watch.
+        path: String,
+        /// File to which the parsed data is appended. The data is printed when not given.
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Database file in which the quotes are stored, rather than printing them.
+        #[cfg(feature = "sqlite")]
+        #[arg(short, long, conflicts_with = "output")]
+        db: Option<String>,
+        #[command(flatten)]
+        discovery: DiscoveryArgs,
+        #[command(flatten)]
+        layout: LayoutArgs,
+    },
+}
+
+#[derive(ClapArgs, Debug)]
+struct InputArgs {
+    /// Directory to search for text data files, or a single data file or archive.
+    path: String,
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+    #[command(flatten)]
+    layout: LayoutArgs,
+    /// Number of data files parsed at the same time [default: number of CPUs].
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Print a report of the processed files to the standard error at the end of the run.
+    #[arg(long, value_enum)]
+    report: Option<ReportFormat>,
+}
+
+#[derive(ClapArgs, Debug, Clone)]
+struct DiscoveryArgs {
+    /// Beginning of the names of the data files [default: data_ibex].
+    #[arg(long, conflicts_with = "by_content")]
+    name_prefix: Option<String>,
+    /// Extension of the data files [default: csv and html, or any extension with --by-content].
+    #[arg(long)]
+    ext: Option<String>,
+    /// Find the data files by their content rather than by their names: every file whose first
+    /// lines contain the tables of the layout is parsed, whatever its name.
+    #[arg(long)]
+    by_content: bool,
+}
+
+#[derive(ClapArgs, Debug)]
+struct OutputArgs {
+    /// Directory to which the data is written, split into files (see --partition-by), rather
+    /// than printing it. Existing files are appended to, with no duplicated entries.
+    #[arg(long)]
+    out_dir: Option<String>,
+    /// How the data is split into files.
+    #[arg(long, value_enum, requires = "out_dir", default_value_t = PartitionBy::TickerDate)]
+    partition_by: PartitionBy,
+}
+
+#[derive(ClapArgs, Debug)]
+struct LayoutArgs {
+    /// Page of BME's web from which the data files were saved [default: auto, which detects
+    /// the page of each data file].
+    #[arg(short, long, value_parser = PossibleValuesParser::new(
+        Layout::builtin_names().into_iter().chain([AUTO_LAYOUT])
+    ))]
+    layout: Option<String>,
+    /// Profile file (TOML or JSON) that describes the layout of the data files. When the option
+    /// is repeated, or combined with "--layout auto", the layout of each data file is detected
+    /// among the given profiles and the built-in layouts. A single profile is used for all the
+    /// data files.
+    #[arg(short, long)]
+    profile: Vec<String>,
+    /// Reject the entire data file when any of its rows can't be parsed, rather than skipping
+    /// those rows.
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    /// CSV with a header, ISO dates and "." as decimal mark.
+    Csv,
+    /// An array of JSON objects.
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ReportFormat {
+    /// A line per file, and the totals of the run.
+    Text,
+    /// A JSON object with the totals of the run and an array with the files.
+    Json,
+}
+
+/// Where the data parsed by the subcommand `watch` goes.
+enum Sink {
+    /// The parsed data is written with CSV format.
+    Output(Box<dyn Write>),
+    /// The quotes are stored in a database.
+    #[cfg(feature = "sqlite")]
+    Storage(Storage),
+}
+
+/// The parser used for a data file, and the number of records found, the errors of the rows
+/// that were skipped, and the result of the conversion of the records. `None` when the layout
+/// of the data file is not detected.
+type Parsed<'a, T> = Option<(&'a IbexParser, Result<(usize, Vec<ParseError>, T), ParseError>)>;
+
+/// How the layout of the data files is chosen.
+enum Layouts {
+    /// All the data files share the same layout.
+    Fixed(IbexParser),
+    /// The layout of each data file is detected.
+    Auto(Detector),
+}
+
+impl DiscoveryArgs {
+    /// Extensions of the data files searched by name.
+    fn formats(&self) -> Vec<Option<&str>> {
+        match self.ext.as_deref() {
+            Some(x) => vec![Some(x)],
+            // Data files might be saved as text or as HTML.
+            None => vec![None, Some("html")],
+        }
+    }
+
+    /// Check whether a file might be a data file, or an archive, using its name.
+    fn accepts(&self, path: &Path) -> bool {
+        if self.by_content {
+            let extension = path.extension().and_then(|x| x.to_str());
+            return ArchiveKind::from_path(path).is_some()
+                || self.ext.is_none()
+                || extension == self.ext.as_deref();
+        }
+
+        self.formats().into_iter().any(|x| is_data_file(path, self.name_prefix.as_deref(), x))
+    }
+
+    /// Find the data files and archives in a directory.
+    fn discover(&self, dir: &Path, layouts: &Layouts) -> Vec<String> {
+        if self.by_content {
+            return discover_by_content(dir, self.ext.as_deref(), &layouts.candidates());
+        }
+
+        // Archives are found by every search.
+        let mut files: Vec<String> = Vec::new();
+        for x in self.formats().into_iter().flat_map(|x| discover(dir, self.name_prefix.as_deref(), x)) {
+            if !files.contains(&x) {
+                files.push(x);
+            }
+        }
+
+        files
+    }
+
+    /// Check whether a file is a data file of the layouts in use, or an archive, using its
+    /// content. Only the first lines of a text file are read (see [sniff_file]).
+    fn is_data_file(&self, path: &Path, layouts: &Layouts) -> io::Result<bool> {
+        if ArchiveKind::from_path(path).is_some() {
+            return Ok(true);
+        }
+
+        layouts.sniff_file(path)
+    }
+
+    /// Extract the data files stored in an archive.
+    ///
+    /// ## Return
+    ///
+    /// The data files, and the names of the files that are accepted by their names but don't
+    /// contain stock data.
+    fn entries(&self, path: &Path, layouts: &Layouts) -> io::Result<(Vec<ArchiveEntry>, Vec<String>)> {
+        let (data, other) = read_all(path)?
+            .into_iter()
+            .filter(|x| self.accepts(Path::new(&x.name)))
+            .partition(|x| layouts.sniff(&x.data));
+
+        Ok((data, other.into_iter().map(|x: ArchiveEntry| x.name).collect()))
+    }
+}
+
+impl LayoutArgs {
+    fn layouts(&self) -> Layouts {
+        let mut profiles = Vec::new();
+
+        for x in self.profile.iter() {
+            match profile::load(Path::new(x)) {
+                Ok(layout) => profiles.push(layout),
+                Err(e) => exit_with(&e.to_string()),
+            }
+        }
+
+        let mode = if self.strict { Mode::Strict } else { Mode::Lenient };
+
+        match self.layout.as_deref() {
+            Some(AUTO_LAYOUT) => Layouts::Auto(Detector::with_profiles(profiles).with_mode(mode)),
+            Some(_) if !profiles.is_empty() => {
+                exit_with(&format!("The option --profile can only be combined with --layout {AUTO_LAYOUT}"))
+            },
+            Some(x) => Layouts::Fixed(IbexParser::with_layout(Layout::builtin(x).unwrap()).with_mode(mode)),
+            None if profiles.len() == 1 => {
+                Layouts::Fixed(IbexParser::with_layout(profiles.pop().unwrap()).with_mode(mode))
+            },
+            None => Layouts::Auto(Detector::with_profiles(profiles).with_mode(mode)),
+        }
+    }
+}
+
+impl Layouts {
+    /// Get the parser for the content of a data file.
+    fn parser(&self, data: &[u8]) -> Option<&IbexParser> {
+        match self {
+            Layouts::Fixed(x) => Some(x),
+            Layouts::Auto(x) => x.detect_bytes(data),
+        }
+    }
+
+    /// The layouts in use.
+    fn candidates(&self) -> Vec<Layout> {
+        match self {
+            Layouts::Fixed(x) => vec![x.layout().clone()],
+            Layouts::Auto(x) => x.layouts().cloned().collect(),
+        }
+    }
+
+    /// Check cheaply whether some content is a data file of the layouts in use.
+    fn sniff(&self, data: &[u8]) -> bool {
+        match self {
+            Layouts::Fixed(x) => sniff(data, [x.layout()]).is_some(),
+            Layouts::Auto(x) => x.sniff(data).is_some(),
+        }
+    }
+
+    /// Check cheaply whether a file is a data file of the layouts in use.
+    fn sniff_file(&self, path: &Path) -> io::Result<bool> {
+        match self {
+            Layouts::Fixed(x) => sniff_file(path, [x.layout()]).map(|x| x.is_some()),
+            Layouts::Auto(x) => sniff_file(path, x.layouts()).map(|x| x.is_some()),
+        }
+    }
+
+    /// Parse the content of a data file into a snapshot of the market, checking the units
+    /// stated by the notes of the page (see [Snapshot::from_quotes]).
+    fn snapshot(&self, data: &[u8]) -> Parsed<'_, Snapshot> {
+        self.parse_with(data, |x, content, layout| {
+            let quotes = Quotes::from_records(x).ok_or(ParseError::NotQuotes)?;
+            Snapshot::from_quotes(quotes, content, layout)
+        })
+    }
+
+    /// Parse the content of a data file with the parser of its layout, and convert its
+    /// records with `f`.
+    fn parse<T>(&self, data: &[u8], f: impl FnOnce(&Records) -> Result<T, ParseError>) -> Parsed<'_, T> {
+        self.parse_with(data, |x, _, _| f(x))
+    }
+
+    /// Parse the content of a data file as `parse`, but `f` also gets the decoded content of
+    /// the file and the layout that was used.
+    fn parse_with<T>(
+        &self,
+        data: &[u8],
+        f: impl FnOnce(&Records, &str, &Layout) -> Result<T, ParseError>
+    ) -> Parsed<'_, T> {
+        let parser = self.parser(data)?;
+        let content = decode(data);
+        let result = parser.try_with_records(&content, |x| {
+            f(x, &content, parser.layout()).map(|value| (x.len(), x.warnings().cloned().collect(), value))
+        });
+
+        Some((parser, result.and_then(|x| x)))
+    }
+
+    /// Record the diagnostics of a parsed data file, with the layout that was used.
+    ///
+    /// ## Return
+    ///
+    /// The result of the conversion of the records, or the reason why the file was rejected.
+    /// Nothing is recorded when no `diagnostics` are given.
+    fn outcome<T>(
+        &self,
+        file: &str,
+        parsed: Parsed<'_, T>,
+        diagnostics: Option<&mut Diagnostics>
+    ) -> Result<T, String> {
+        let Some((parser, result)) = parsed else {
+            if let Some(x) = diagnostics {
+                x.skipped(file, SkipReason::WrongLayout);
+            }
+            return Err(SkipReason::WrongLayout.to_string());
+        };
+        let layout = &parser.layout().name;
+
+        match result {
+            Ok((rows, warnings, value)) => {
+                for e in warnings.iter() {
+                    eprintln!("File {file} has a row that was skipped: {e}");
+                }
+                if let Some(x) = diagnostics {
+                    x.parsed(file, layout, rows, warnings);
+                }
+                Ok(value)
+            },
+            Err(e) => {
+                let message = e.to_string();
+                if let Some(x) = diagnostics {
+                    x.failed(file, Some(layout), e);
+                }
+                Err(message)
+            },
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
-    let filter: Vec<String> = if let Some(filter) = args.filter.as_deref() {
+
+    match args.command {
+        Some(Command::Parse { input, filter, output }) => parse(&input, filter.as_deref(), &output),
+        Some(Command::Validate { input }) => validate(&input),
+        Some(Command::Bars { input, interval, ticker, session_only }) => {
+            bars(&input, interval, ticker.as_deref(), session_only)
+        },
+        Some(Command::Stats { input }) => stats(&input),
+        Some(Command::Breadth { input, top }) => market_breadth(&input, top),
+        Some(Command::Gaps { input, interval }) => gaps(&input, interval),
+        Some(Command::Export { input, format, output, timing, timestamps }) => {
+            let options = ExportOptions { timing, timestamps };
+            export(&input, format, output.as_deref(), options)
+        },
+        #[cfg(feature = "sqlite")]
+        Some(Command::Ingest { input, db, manifest }) => {
+            ingest(&input, &db, manifest.as_deref())
+        },
+        Some(Command::Watch { path, output, #[cfg(feature = "sqlite")] db, discovery, layout }) => {
+            #[cfg(feature = "sqlite")]
+            if let Some(x) = db {
+                return watch(&path, Sink::Storage(open_storage(&x)), &discovery, &layout.layouts());
+            }
+            watch(&path, Sink::Output(open_output(output.as_deref())), &discovery, &layout.layouts())
+        },
+        None => {
+            let input = InputArgs {
+                path: args.path.unwrap(),
+                discovery: args.discovery,
+                layout: args.layout,
+                jobs: args.jobs,
+                report: args.report,
+            };
+            parse(&input, args.filter.as_deref(), &args.output)
+        },
+    }
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+
+fn interval(value: &str) -> Result<TimeDelta, String> {
+    parse_interval(value).ok_or_else(|| format!("wrong interval {value}, use e.g. 30s, 5m or 1h"))
+}
+
+/// Find the data files and archives under the path given in the input arguments.
+///
+/// Files that don't contain stock data are recorded in `diagnostics`.
+///
+/// ## Return
+///
+/// The name of each file, and its path.
+fn input_files(input: &InputArgs, layouts: &Layouts, diagnostics: &mut Diagnostics) -> Vec<(String, PathBuf)> {
+    let root = Path::new(&input.path);
+
+    // A single file or archive might be given rather than a directory.
+    let (dir, files) = if root.is_file() {
+        let file = root.file_name().unwrap().to_str().unwrap().to_string();
+        (root.parent().unwrap().to_path_buf(), vec![file])
+    } else {
+        (root.to_path_buf(), input.discovery.discover(root, layouts))
+    };
+    // Files found by their content were already inspected.
+    let sniffed = input.discovery.by_content && !root.is_file();
+
+    files.into_iter().filter_map(|x| {
+        let path = dir.join(&x);
+
+        // Avoid passing files with no stock data to the parser. Files that can't be read are
+        // reported when they are visited.
+        if sniffed {
+            return Some((x, path));
+        }
+
+        match input.discovery.is_data_file(&path, layouts) {
+            Ok(false) => {
+                diagnostics.skipped(&x, SkipReason::NotSnapshot);
+                None
+            },
+            _ => Some((x, path)),
+        }
+    }).collect()
+}
+
+/// Call `f` with the name and the content of a data file, or of each data file stored in an
+/// archive. Files that are not parsed are given with the reason why.
+///
+/// Data files are expected to be found by the discovery (see [DiscoveryArgs::is_data_file]),
+/// and only the files stored in archives are checked.
+fn visit_file(
+    file: &str,
+    path: &Path,
+    discovery: &DiscoveryArgs,
+    layouts: &Layouts,
+    f: &mut impl FnMut(&str, Result<&[u8], SkipReason>)
+) {
+    if ArchiveKind::from_path(path).is_some() {
+        let (entries, other) = match discovery.entries(path, layouts) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Archive {file} couldn't be read: {e}");
+                f(file, Err(SkipReason::Unreadable(e.to_string())));
+                return;
+            }
+        };
+
+        for entry in entries {
+            f(&format!("{file}:{}", entry.name), Ok(&entry.data));
+        }
+        for name in other {
+            f(&format!("{file}:{name}"), Err(SkipReason::NotSnapshot));
+        }
+        return;
+    }
+
+    match std::fs::read(path) {
+        Ok(data) => f(file, Ok(&data)),
+        Err(e) => {
+            eprintln!("File {file} couldn't be read: {e}");
+            f(file, Err(SkipReason::Unreadable(e.to_string())));
+        },
+    }
+}
+
+/// Parse the given data files and archives concurrently.
+///
+/// # Description
+///
+/// `parse` is called from several threads with the content of each data file, including the
+/// data files stored in archives. `f` is called with the path of the file or archive, the name
+/// of each data file and the result of `parse`, in the same order as the files were given, so
+/// the output doesn't depend on the number of threads.
+///
+/// Files that don't contain stock data, that can't be read, or whose content is the same as
+/// the one of a previous file, are recorded in `diagnostics` and not given to `f`.
+fn parse_files<T, P, F>(
+    files: &[(String, PathBuf)],
+    input: &InputArgs,
+    layouts: &Layouts,
+    diagnostics: &mut Diagnostics,
+    parse: P,
+    mut f: F
+) where
+    T: Send,
+    P: Fn(&[u8]) -> T + Sync,
+    F: FnMut(&Path, &str, T, &mut Diagnostics),
+{
+    // Name of the first file found with each content.
+    let mut contents: HashMap<[u8; 32], String> = HashMap::new();
+
+    Batch::new(input.jobs).for_each_ordered(
+        files,
+        |(file, path)| {
+            let mut results = Vec::new();
+            visit_file(file, path, &input.discovery, layouts, &mut |file, data| {
+                results.push((String::from(file), data.map(|x| (Sha256::digest(x).into(), parse(x)))));
+            });
+            results
+        },
+        |(_, path), results| {
+            for (file, result) in results {
+                match result {
+                    Ok((digest, _)) if contents.contains_key(&digest) => {
+                        diagnostics.skipped(&file, SkipReason::Duplicate(contents[&digest].clone()));
+                    },
+                    Ok((digest, x)) => {
+                        contents.insert(digest, file.clone());
+                        f(path, &file, x, diagnostics);
+                    },
+                    Err(reason) => diagnostics.skipped(&file, reason),
+                }
+            }
+        },
+    );
+}
+
+/// Print the report of the processed files, when requested.
+fn print_report(format: Option<ReportFormat>, diagnostics: &Diagnostics) {
+    let result = match format {
+        Some(ReportFormat::Text) => diagnostics.write_text(std::io::stderr()),
+        Some(ReportFormat::Json) => diagnostics.write_json(std::io::stderr()),
+        None => return,
+    };
+
+    if let Err(e) = result {
+        exit_with(&format!("The report couldn't be written: {e}"));
+    }
+}
+
+fn parse(input: &InputArgs, filter: Option<&str>, output: &OutputArgs) {
+    let filter: Vec<String> = if let Some(filter) = filter {
         vec![String::from(filter)]
     } else {
         Vec::new()
     };
+    let layouts = input.layout.layouts();
+    let mut diagnostics = Diagnostics::default();
+    let mut writer = output.out_dir.as_ref().map(|x| PartitionWriter::new(Path::new(x), output.partition_by));
 
-    let path = Path::new(&args.path);
-    let files = discover(path, None, None);
-    let parser = IbexParser::new();
+    let parse = |data: &[u8]| {
+        // Only the records that match the filter are copied.
+        layouts.parse_with(data, |x, _, layout| {
+            Ok((x.filter(&filter).map(|x| x.to_owned_record()).collect::<Vec<_>>(), layout.clone()))
+        })
+    };
 
-    for file in files {
-        let file_string = format!("{}/{}",&args.path,file.as_str());
-        let path = Path::new(&file_string);
+    parse_files(&input_files(input, &layouts, &mut diagnostics), input, &layouts, &mut diagnostics, parse, |_, file, parsed, diagnostics| {
+        match (&mut writer, layouts.outcome(file, parsed, Some(diagnostics))) {
+            (Some(writer), Ok((records, layout))) => {
+                if let Err(e) = writer.write(&records, &layout) {
+                    eprintln!("The data of the file {file} couldn't be written: {e}");
+                }
+            },
+            (_, data) => print_data(data.map(|(x, _)| x.iter().map(|x| x.to_string()).collect()), file),
+        }
+    });
 
-        // Avoid passing empty files to the parser.
-        if path.metadata().unwrap().len() < MIN_BYTES_X_FILE {
-            continue;
+    print_report(input.report, &diagnostics);
+}
+
+/// Parse the quotes of the data files under the given path.
+fn load_quotes(input: &InputArgs) -> Vec<Quotes> {
+    load_snapshots(input).into_iter().map(Quotes::from).collect()
+}
+
+/// Parse the snapshots of the data files under the given path.
+fn load_snapshots(input: &InputArgs) -> Vec<Snapshot> {
+    let layouts = input.layout.layouts();
+    let calendar = Calendar::new();
+    let mut diagnostics = Diagnostics::default();
+    let mut snapshots = Vec::new();
+
+    let parse = |data: &[u8]| layouts.snapshot(data);
+
+    parse_files(&input_files(input, &layouts, &mut diagnostics), input, &layouts, &mut diagnostics, parse, |path, file, parsed, diagnostics| {
+        match layouts.outcome(file, parsed, Some(diagnostics)) {
+            Ok(x) => {
+                // The capture time of the files of an archive is unknown.
+                let captured_at = match ArchiveKind::from_path(path) {
+                    Some(_) => None,
+                    None => path.metadata().and_then(|x| x.modified()).ok().map(local_time),
+                };
+                let snapshot = Snapshot { source: Some(String::from(file)), captured_at, ..x };
+
+                // Pages captured after the close show the closing prices of the session.
+                let stale = |x: &Phase| matches!(x, Phase::NonTradingDay | Phase::BeforeOpen);
+                if let Some(phase) = snapshot.capture_time().map(|x| calendar.phase(x)).filter(stale) {
+                    eprintln!("File {file} was captured out of a session of the market: {phase}");
+                }
+                snapshots.push(snapshot)
+            },
+            Err(e) => eprintln!("File {file} doesn't contain valid data: {e}"),
+        }
+    });
+
+    print_report(input.report, &diagnostics);
+
+    snapshots
+}
+
+fn validate(input: &InputArgs) {
+    let layouts = input.layout.layouts();
+    let mut diagnostics = Diagnostics::default();
+    let (mut n_valid, mut n_invalid) = (0, 0);
+
+    let parse = |data: &[u8]| layouts.parse(data, |x| Ok(x.len()));
+
+    parse_files(&input_files(input, &layouts, &mut diagnostics), input, &layouts, &mut diagnostics, parse, |_, file, parsed, diagnostics| {
+        let layout = parsed.as_ref().map(|(x, _)| x.layout().name.clone()).unwrap_or_default();
+
+        match layouts.outcome(file, parsed, Some(diagnostics)) {
+            Ok(entries) => {
+                n_valid += 1;
+                println!("File {file} is valid: layout {layout}, {entries} entries");
+            },
+            Err(e) => {
+                n_invalid += 1;
+                println!("File {file} doesn't contain valid data: {e}");
+            },
         }
-        let data = parser.filter_file(path , &filter);
+    });
+
+    println!("{n_valid} valid files, {n_invalid} invalid files");
+    print_report(input.report, &diagnostics);
+
+    if n_invalid > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn bars(input: &InputArgs, interval: TimeDelta, ticker: Option<&str>, session_only: bool) {
+    let stocks: Vec<_> = load_quotes(input)
+        .into_iter()
+        .flat_map(|x| x.stocks)
+        .filter(|x| ticker.is_none_or(|t| x.ticker == t))
+        .collect();
+
+    println!("ticker;date;start;open;high;low;close;volume;turnover");
+
+    let bars = if session_only {
+        aggregate_session(&stocks, interval, &Calendar::new())
+    } else {
+        aggregate(&stocks, interval)
+    };
+
+    for bar in bars {
+        println!(
+            "{};{};{};{};{};{};{};{};{}",
+            bar.ticker,
+            bar.date.format("%d/%m/%Y"),
+            bar.start.format("%H:%M:%S"),
+            format_decimal(bar.open, 4),
+            format_decimal(bar.high, 4),
+            format_decimal(bar.low, 4),
+            format_decimal(bar.close, 4),
+            format_integer(bar.volume),
+            format_decimal(bar.turnover, 2),
+        );
+    }
+}
+
+fn stats(input: &InputArgs) {
+    let stocks: Vec<_> = load_quotes(input).into_iter().flat_map(|x| x.stocks).collect();
+
+    println!("ticker;quotes;sessions;first;last;open;high;low;close;change");
+
+    for x in summarize(&stocks) {
+        println!(
+            "{};{};{};{};{};{};{};{};{};{}",
+            x.ticker,
+            x.quotes,
+            x.sessions,
+            x.first.format("%d/%m/%Y"),
+            x.last.format("%d/%m/%Y"),
+            format_decimal(x.open, 4),
+            format_decimal(x.high, 4),
+            format_decimal(x.low, 4),
+            format_decimal(x.close, 4),
+            x.change.map(|x| format!("{}%", format_decimal(x, 2))).unwrap_or_default(),
+        );
+    }
+}
+
+fn market_breadth(input: &InputArgs, top: usize) {
+    let snapshots = load_snapshots(input);
+    let movers = |x: &[Mover]| {
+        x.iter().map(|x| format!("{} {}%", x.ticker, format_decimal(x.change, 2))).collect::<Vec<_>>().join("|")
+    };
+
+    println!("file;captured_at;index;advancers;decliners;unchanged;unpriced;above_open;gainers;losers;turnover_share");
+
+    for x in breadth(&snapshots, top) {
+        let turnover_share: Vec<String> = x.turnover_share
+            .iter()
+            .map(|(ticker, share)| format!("{ticker} {}%", format_decimal(*share, 2)))
+            .collect();
+
+        println!(
+            "{};{};{};{};{};{};{};{}%;{};{};{}",
+            x.source.as_deref().unwrap_or_default(),
+            x.captured_at.map(|t| t.format("%d/%m/%Y %H:%M:%S").to_string()).unwrap_or_default(),
+            x.index.map(|i| format_decimal(i, 2)).unwrap_or_default(),
+            x.advancers,
+            x.decliners,
+            x.unchanged,
+            x.unpriced,
+            format_decimal(x.above_open, 2),
+            movers(&x.gainers),
+            movers(&x.losers),
+            turnover_share.join("|"),
+        );
+    }
+}
+
+fn gaps(input: &InputArgs, interval: TimeDelta) {
+    let report = find_gaps(&load_snapshots(input), interval, &Calendar::new());
+    let time = |x: &chrono::NaiveDateTime| x.format("%d/%m/%Y %H:%M:%S");
+
+    for x in report.gaps.iter() {
+        println!("Gap from {} to {}: {} data files missing", time(&x.from), x.to.format("%H:%M:%S"), x.missing);
+    }
+    for x in report.duplicates.iter() {
+        println!("Data files captured at {}: {}", time(&x.captured_at), x.sources.join(", "));
+    }
+    for x in report.stale.iter() {
+        println!(
+            "Stock {} stuck at {} from {} to {} ({} data files)",
+            x.ticker,
+            x.published_at.format("%H:%M:%S"),
+            time(&x.from),
+            x.to.format("%H:%M:%S"),
+            x.snapshots,
+        );
+    }
+
+    println!(
+        "{} data files: {} gaps ({} data files missing), {} duplicated capture times, {} stale stocks",
+        report.snapshots,
+        report.gaps.len(),
+        report.missing(),
+        report.duplicates.len(),
+        report.stale.len(),
+    );
+    if report.untimed > 0 {
+        println!("{} data files with no capture time were ignored", report.untimed);
+    }
+}
+
+fn export(input: &InputArgs, format: ExportFormat, output: Option<&str>, options: ExportOptions) {
+    let snapshots = load_snapshots(input);
+    let out = match output {
+        Some(x) => match std::fs::File::create(x) {
+            Ok(file) => Box::new(file) as Box<dyn Write>,
+            Err(e) => exit_with(&format!("File {x} couldn't be created: {e}")),
+        },
+        None => Box::new(std::io::stdout()),
+    };
+
+    let result = match format {
+        ExportFormat::Csv => write_snapshots_csv(out, &snapshots, options),
+        ExportFormat::Json => write_snapshots_json(out, &snapshots, options),
+    };
+
+    if let Err(e) = result {
+        exit_with(&format!("The data couldn't be exported: {e}"));
+    }
+}
 
-        match data {
-            Some(x) => {
-                for line in x {
-                    println!("{}", line);
+fn print_data(data: Result<Vec<String>, String>, file: &str) {
+    match data {
+        Ok(x) => {
+            for line in x {
+                println!("{}", line);
+            }
+        },
+        Err(e) => println!("File {file} doesn't contain valid data: {e}")
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn ingest(input: &InputArgs, db: &str, manifest_path: Option<&str>) {
+    let layouts = input.layout.layouts();
+    let mut storage = open_storage(db);
+    let mut manifest = match manifest_path.map(|x| Manifest::load(Path::new(x))).transpose() {
+        Ok(x) => x,
+        Err(e) => exit_with(&format!("Manifest {} couldn't be read: {e}", manifest_path.unwrap())),
+    };
+    let mut diagnostics = Diagnostics::default();
+    let (mut n_files, mut n_quotes, mut n_unchanged) = (0, 0, 0);
+    let (mut files, mut records) = (Vec::new(), Vec::new());
+
+    for (file, path) in input_files(input, &layouts, &mut diagnostics) {
+        // Files that didn't change since the last run are not parsed again.
+        match manifest.as_ref().map(|x| x.check(&path)).transpose() {
+            Ok(Some((Change::Unchanged, record))) => {
+                // The record may carry a new time of the last modification.
+                n_unchanged += 1;
+                records.push((path, record));
+            },
+            Ok(x) => {
+                records.extend(x.map(|(_, record)| (path.clone(), record)));
+                files.push((file, path));
+            },
+            Err(e) => eprintln!("File {file} couldn't be read: {e}"),
+        }
+    }
+
+    let parse = |data: &[u8]| layouts.snapshot(data);
+    // Files that couldn't be parsed or whose quotes couldn't be stored are not recorded in
+    // the manifest, so they are tried again on the next run.
+    let mut not_stored = std::collections::HashSet::new();
+
+    parse_files(&files, input, &layouts, &mut diagnostics, parse, |path, file, parsed, diagnostics| {
+        match layouts.outcome(file, parsed, Some(diagnostics)).map(|x| storage.store(&Quotes::from(x))) {
+            Ok(Ok(x)) => {
+                n_files += 1;
+                n_quotes += x;
+            },
+            Ok(Err(e)) => {
+                println!("File {file} couldn't be stored: {e}");
+                not_stored.insert(path.to_path_buf());
+            },
+            Err(e) => {
+                println!("File {file} doesn't contain valid data: {e}");
+                not_stored.insert(path.to_path_buf());
+            },
+        }
+    });
+
+    if let Some(manifest) = manifest.as_mut() {
+        for (path, record) in records.into_iter().filter(|(x, _)| !not_stored.contains(x)) {
+            manifest.insert(&path, record);
+        }
+    }
+
+    if let (Some(manifest), Some(x)) = (manifest, manifest_path) {
+        if let Err(e) = manifest.save(Path::new(x)) {
+            exit_with(&format!("Manifest {x} couldn't be saved: {e}"));
+        }
+    }
+
+    println!("Stored {n_quotes} quotes from {n_files} files into {db}");
+    if n_unchanged > 0 {
+        println!("Skipped {n_unchanged} files that didn't change since the last run");
+    }
+    print_report(input.report, &diagnostics);
+}
+
+#[cfg(feature = "sqlite")]
+fn open_storage(db: &str) -> Storage {
+    match Storage::open(Path::new(db)) {
+        Ok(x) => x,
+        Err(e) => exit_with(&format!("Database {db} couldn't be opened: {e}")),
+    }
+}
+
+/// Open the file to which the parsed data is appended, or the standard output.
+fn open_output(output: Option<&str>) -> Box<dyn Write> {
+    match output {
+        Some(x) => match OpenOptions::new().create(true).append(true).open(x) {
+            Ok(file) => Box::new(file),
+            Err(e) => exit_with(&format!("File {x} couldn't be opened: {e}")),
+        },
+        None => Box::new(std::io::stdout()),
+    }
+}
+
+impl Sink {
+    fn write(&mut self, layouts: &Layouts, data: &[u8], file: &str) {
+        match self {
+            Sink::Output(out) => {
+                let parsed = layouts.parse(data, |x| Ok(x.to_entries()));
+                let lines = match layouts.outcome(file, parsed, None) {
+                    Ok(x) => x,
+                    Err(e) => return eprintln!("File {file} doesn't contain valid data: {e}"),
+                };
+                let result = lines.iter().try_for_each(|x| writeln!(out, "{x}")).and_then(|_| out.flush());
+
+                if let Err(e) = result {
+                    exit_with(&format!("The data of the file {file} couldn't be written: {e}"));
+                }
+            },
+            #[cfg(feature = "sqlite")]
+            Sink::Storage(storage) => {
+                let parsed = layouts.snapshot(data);
+
+                match layouts.outcome(file, parsed, None).map(|x| storage.store(&Quotes::from(x))) {
+                    Ok(Ok(x)) => eprintln!("Stored {x} quotes from {file}"),
+                    Ok(Err(e)) => eprintln!("File {file} couldn't be stored: {e}"),
+                    Err(e) => eprintln!("File {file} doesn't contain valid data: {e}"),
                 }
             },
-            None => println!("File {file} doesn't contain valid data.")
         }
     }
 }
+
+fn watch(path: &str, mut sink: Sink, discovery: &DiscoveryArgs, layouts: &Layouts) {
+    let accept = {
+        let discovery = discovery.clone();
+        move |x: &Path| discovery.accepts(x)
+    };
+    let mut watcher = match DirWatcher::new(Path::new(path), accept) {
+        Ok(x) => x,
+        Err(e) => exit_with(&format!("Directory {path} couldn't be watched: {e}")),
+    };
+    // Files that are written again with the same content are not parsed again.
+    let mut manifest = Manifest::default();
+
+    eprintln!("Watching {path} for new data files");
+
+    while let Some(file_path) = watcher.next_file(None) {
+        match manifest.check(&file_path) {
+            Ok((Change::Unchanged, record)) => {
+                manifest.insert(&file_path, record);
+                continue;
+            },
+            Ok((_, record)) => manifest.insert(&file_path, record),
+            Err(e) => {
+                eprintln!("File {} couldn't be read: {e}", file_path.display());
+                continue;
+            },
+        }
+
+        let file = file_path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if let Ok(false) = discovery.is_data_file(&file_path, layouts) {
+            eprintln!("File {file} was skipped: {}", SkipReason::NotSnapshot);
+            continue;
+        }
+
+        visit_file(&file, &file_path, discovery, layouts, &mut |file, data| match data {
+            Ok(x) => sink.write(layouts, x, file),
+            Err(reason) => eprintln!("File {file} was skipped: {reason}"),
+        });
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    // A data file that can't be parsed is not recorded in the manifest, so it's parsed again on
+    // the next run.
+    #[rstest]
+    fn test_ingest_retry() {
+        let dir = std::env::temp_dir().join(format!("ibex_parser_ingest_{}", std::process::id()));
+        let (db, manifest) = (dir.join("quotes.db"), dir.join("manifest.json"));
+        let (good, bad) = (dir.join("data").join("data_ibex.csv"), dir.join("data").join("data_ibex(1).csv"));
+        let content = std::fs::read_to_string("./tests/data/data_ibex.csv").unwrap();
+
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(&good, &content).unwrap();
+        std::fs::write(&bad, content.replacen("06/02/2024\tCierre", "06/02/2024\tx", 1)).unwrap();
+
+        let run = || {
+            let args = Args::parse_from([
+                "ibex_parser", "ingest", "--layout", "ibex35", "--strict",
+                "--db", db.to_str().unwrap(),
+                "--manifest", manifest.to_str().unwrap(),
+                dir.join("data").to_str().unwrap(),
+            ]);
+            let Some(Command::Ingest { input, db, manifest }) = args.command else { unreachable!() };
+            ingest(&input, &db, manifest.as_deref());
+            Manifest::load(Path::new(&manifest.unwrap())).unwrap()
+        };
+
+        let first = run();
+        assert!(first.get(&good).is_some());
+        assert!(first.get(&bad).is_none());
+
+        std::fs::write(&bad, &content).unwrap();
+        assert!(run().get(&bad).is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2024 Felipe Torres González
+
+//! Manifest of the data files that were already processed.
+//!
+//! A directory that receives a new data file every few minutes grows quickly, and parsing
+//! all its files on each run is a waste of time. The manifest records the size, the time of
+//! the last modification and a hash of the content of each processed file, so only new or
+//! changed files need to be parsed again.
+//!
+//! The manifest is saved as a JSON file:
+//!
+//! ```json
+//! {
+//!   "files": {
+//!     "/srv/snapshots/data_ibex.csv": {
+//!       "size": 4182,
+//!       "mtime": 1707236223000000000,
+//!       "hash": "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//!     }
+//!   }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+/// What is known about a processed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Time of the last modification of the file, in nanoseconds since the Unix epoch.
+    pub mtime: u64,
+    /// SHA-256 hash of the content of the file, as a hexadecimal string.
+    pub hash: String,
+}
+
+/// Result of checking a file against the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// The file is not found in the manifest.
+    New,
+    /// The content of the file changed since it was processed.
+    Modified,
+    /// The content of the file didn't change since it was processed.
+    Unchanged,
+}
+
+/// A set of processed files, identified by their path.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::manifest::{Change, Manifest};
+/// use std::path::Path;
+///
+/// let mut manifest = Manifest::default();
+/// let path = Path::new("./tests/data/data_ibex.csv");
+///
+/// let (change, record) = manifest.check(path).unwrap();
+/// assert_eq!(change, Change::New);
+///
+/// // Parse the file, then record it.
+/// manifest.insert(path, record);
+/// assert_eq!(manifest.check(path).unwrap().0, Change::Unchanged);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    files: BTreeMap<String, FileRecord>,
+}
+
+/// Files are identified by their canonical path, so `./x` and `/abs/x` are the same file.
+fn key(path: &Path) -> String {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().into_owned()
+}
+
+/// Size and time of the last modification of a file.
+fn stat(path: &Path) -> io::Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or_default();
+
+    Ok((metadata.len(), mtime))
+}
+
+fn hash(path: &Path) -> io::Result<String> {
+    Ok(format!("{:x}", Sha256::digest(fs::read(path)?)))
+}
+
+impl FileRecord {
+    /// Build the record of a file from its metadata and its content.
+    pub fn from_file(path: &Path) -> io::Result<FileRecord> {
+        let (size, mtime) = stat(path)?;
+
+        Ok(FileRecord { size, mtime, hash: hash(path)? })
+    }
+
+    // The manifest is read and written through JSON values, so it doesn't depend on the
+    // feature `serde`.
+    fn from_value(value: &Value) -> Option<FileRecord> {
+        Some(FileRecord {
+            size: value.get("size")?.as_u64()?,
+            mtime: value.get("mtime")?.as_u64()?,
+            hash: String::from(value.get("hash")?.as_str()?),
+        })
+    }
+
+    fn to_value(&self) -> Value {
+        json!({ "size": self.size, "mtime": self.mtime, "hash": self.hash })
+    }
+}
+
+impl Manifest {
+    /// Load a manifest from a JSON file.
+    ///
+    /// An empty manifest is returned when the file doesn't exist, e.g. on the first run.
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        match fs::read_to_string(path) {
+            Ok(x) => {
+                let value: Value = serde_json::from_str(&x).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let files = value
+                    .get("files")
+                    .and_then(Value::as_object)
+                    .and_then(|x| x.iter().map(|(k, v)| Some((k.clone(), FileRecord::from_value(v)?))).collect())
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "wrong manifest"))?;
+
+                Ok(Manifest { files })
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the manifest to a JSON file.
+    ///
+    /// The manifest is written to a temporary file that replaces the given file at the end, so
+    /// an interrupted run doesn't leave a broken manifest behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let files: Map<String, Value> = self.files.iter().map(|(k, v)| (k.clone(), v.to_value())).collect();
+        let content = serde_json::to_string_pretty(&json!({ "files": files })).map_err(io::Error::other)?;
+
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Check whether a file changed since it was recorded.
+    ///
+    /// # Description
+    ///
+    /// A file whose size and time of the last modification match the record is not read.
+    /// Otherwise, its content is hashed and compared with the recorded hash, so a file that
+    /// is written again with the same content is reported as unchanged.
+    ///
+    /// ## Return
+    ///
+    /// The kind of change, and the current record of the file. The record should be inserted
+    /// in the manifest once the file is processed, and also when the file is unchanged, so a
+    /// new time of the last modification is stored and the file isn't hashed on every run.
+    pub fn check(&self, path: &Path) -> io::Result<(Change, FileRecord)> {
+        let (size, mtime) = stat(path)?;
+        let recorded = self.files.get(&key(path));
+
+        if let Some(x) = recorded.filter(|x| x.size == size && x.mtime == mtime) {
+            return Ok((Change::Unchanged, x.clone()));
+        }
+
+        let record = FileRecord { size, mtime, hash: hash(path)? };
+        let change = match recorded {
+            None => Change::New,
+            Some(x) if x.size == record.size && x.hash == record.hash => Change::Unchanged,
+            Some(_) => Change::Modified,
+        };
+
+        Ok((change, record))
+    }
+
+    /// Record a processed file, replacing its previous record.
+    pub fn insert(&mut self, path: &Path, record: FileRecord) {
+        self.files.insert(key(path), record);
+    }
+
+    /// Get the record of a file.
+    pub fn get(&self, path: &Path) -> Option<&FileRecord> {
+        self.files.get(&key(path))
+    }
+
+    /// Number of recorded files.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether no file is recorded.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use crate::testing::*;
+
+    #[rstest]
+    fn test_manifest_check(tmp: TempDir) {
+        let path = tmp.0.join("data_ibex.csv");
+        let mut manifest = Manifest::default();
+
+        fs::write(&path, "IBEX 35").unwrap();
+        let (change, record) = manifest.check(&path).unwrap();
+        assert_eq!(change, Change::New);
+        assert_eq!(record.size, 7);
+        manifest.insert(&path, record);
+
+        // Writing the same content again doesn't change the file.
+        fs::write(&path, "IBEX 35").unwrap();
+        let (change, record) = manifest.check(&path).unwrap();
+        assert_eq!(change, Change::Unchanged);
+        manifest.insert(&path, record.clone());
+        assert_eq!(manifest.get(&path), Some(&record));
+
+        // Same size, but a different content and time of the last modification.
+        fs::write(&path, "IBEX 36").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(UNIX_EPOCH).unwrap();
+        assert_eq!(manifest.check(&path).unwrap().0, Change::Modified);
+    }
+
+    #[rstest]
+    fn test_manifest_save(tmp: TempDir) {
+        let path = tmp.0.join("manifest.json");
+        let mut manifest = Manifest::load(&path).unwrap();
+        assert!(manifest.is_empty());
+
+        let data = Path::new("./tests/data/data_ibex.csv");
+        manifest.insert(data, FileRecord::from_file(data).unwrap());
+        manifest.save(&path).unwrap();
+
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.get(data).unwrap().hash.len(), 64);
+    }
+
+    #[rstest]
+    fn test_manifest_key() {
+        let mut manifest = Manifest::default();
+        let data = Path::new("./tests/data/data_ibex.csv");
+        manifest.insert(data, FileRecord::from_file(data).unwrap());
+
+        let absolute = fs::canonicalize(data).unwrap();
+        assert!(manifest.get(&absolute).is_some());
+        assert!(manifest.get(Path::new("tests/data/../data/data_ibex.csv")).is_some());
+        assert_eq!(manifest.len(), 1);
+    }
+
+    #[rstest]
+    fn test_manifest_wrong(tmp: TempDir) {
+        let path = tmp.0.join("manifest.json");
+
+        fs::write(&path, "data_ibex.csv").unwrap();
+        assert_eq!(Manifest::load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::write(&path, r#"{"files": {"data_ibex.csv": {"size": 7}}}"#).unwrap();
+        assert_eq!(Manifest::load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2024 Felipe Torres González
+
+//! Normalisation of the raw content of data files.
+//!
+//! Data files are produced copying the content of a web page, so their content depends on
+//! the browser and the editor used to save them. This module turns all the flavours found
+//! so far into plain UTF-8 text with `\n` line endings.
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+/// Decode the raw content of a data file into normalised text.
+///
+/// # Description
+///
+/// The following transformations are applied to the content:
+/// - A byte order mark (BOM) at the beginning of the content is removed. UTF-16 content is
+///   decoded when it starts with a BOM.
+/// - Content that is not valid UTF-8 is decoded as Windows-1252, which is a superset of
+///   Latin-1 (ISO-8859-1). That's the encoding used by most editors on Windows, and it
+///   keeps characters such as `Í`, `Ú` or `€` that are found in the data files.
+/// - Line endings are converted to `\n`, whether the original ones were `\r\n` or `\r`.
+/// - The quotes that wrap the entire content when it is copied from some browsers are
+///   removed.
+///
+/// ## Return
+///
+/// A `String` with the normalised content. This function never fails: bytes that can't be
+/// decoded are replaced by `U+FFFD`.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::normalize::decode;
+///
+/// let raw = b"\xef\xbb\xbf\"Mercado\r\n\xc3\x8dndice\r\n\"";
+/// assert_eq!(decode(raw), "Mercado\n\u{cd}ndice\n");
+///
+/// let raw = b"\xcdndice\t\xdaltimo\r\n";
+/// assert_eq!(decode(raw), "\u{cd}ndice\t\u{da}ltimo\n");
+/// ```
+pub fn decode(data: &[u8]) -> String {
+    let text = match Encoding::for_bom(data) {
+        Some((encoding, bom_len)) => encoding.decode_without_bom_handling(&data[bom_len..]).0,
+        None => match std::str::from_utf8(data) {
+            Ok(_) => UTF_8.decode_without_bom_handling(data).0,
+            Err(_) => WINDOWS_1252.decode_without_bom_handling(data).0,
+        },
+    };
+
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+
+    strip_quotes(text)
+}
+
+/// Remove the quotes that wrap the entire content of a data file.
+///
+/// Only a pair of quotes at both ends of the content is removed, quotes found in between are
+/// kept as they are.
+fn strip_quotes(text: String) -> String {
+    let trimmed = text.trim_end();
+
+    if trimmed.len() > 1 && trimmed.starts_with('"') && trimmed.ends_with('"') {
+        let mut result = String::from(&trimmed[1..trimmed.len() - 1]);
+        // Keep the original line ending of the last line.
+        result.push_str(&text[trimmed.len()..]);
+        result
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use std::fs::read;
+
+    #[rstest]
+    #[case(b"Nombre\tUltimo\n", "Nombre\tUltimo\n")]
+    #[case(b"\xef\xbb\xbfNombre\n", "Nombre\n")]
+    #[case(b"Nombre\r\nACS\r\n", "Nombre\nACS\n")]
+    #[case(b"Nombre\rACS\r", "Nombre\nACS\n")]
+    #[case(b"\"Mercado\nhist\xc3\xb3rica.\"", "Mercado\nhist\u{f3}rica.")]
+    #[case(b"\"Mercado", "\"Mercado")]
+    #[case(b"Efectivo (miles \x80)", "Efectivo (miles \u{20ac})")]
+    #[case(b"\xff\xfeA\x00\n\x00", "A\n")]
+    fn test_decode(#[case] raw: &[u8], #[case] expected: &str) {
+        assert_eq!(decode(raw), expected);
+    }
+
+    // A file saved on Windows must result in the same text as the original file.
+    #[rstest]
+    fn test_decode_windows_file() {
+        let original = decode(&read("./tests/data/data_ibex.csv").unwrap());
+        let windows = decode(&read("./tests/data/data_ibex_win.csv").unwrap());
+
+        assert!(original.starts_with("Mercado\n"));
+        assert_eq!(windows, original);
+    }
+}
@@ -1,12 +1,17 @@
 // Copyright 2024 Felipe Torres González
 
+use std::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::path::Path;
-use std::fs::read_to_string;
-
-/// How many stock prices are included in a raw text file.
-const N_STOCKS_IN_RAW_FILE: usize = 36;
-/// How many lines has a common raw text data file.
-const N_LINES_PER_RAW_FILE: usize = 51;
+use std::fs::{metadata, read};
+use crate::normalize::decode;
+use crate::html::{self, is_html};
+use crate::layout::{Bounds, Column, ColumnRef, Layout};
+use crate::quote::Quotes;
+use crate::snapshot::Snapshot;
+use crate::record::{Cells, Records, Table};
+use crate::timezone::local_time;
 
 /// A custom type that identifies an array of strings that will be used to filter results.
 type StockFilter = Vec<String>;
@@ -14,6 +19,64 @@ type StockFilter = Vec<String>;
 /// A custom type that identifies an array that includes stock data.
 type StockData = Vec<String>;
 
+/// The reason why the content of a data file couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// No table whose first column has the given name was found.
+    TableNotFound(String),
+    /// The table whose first column has the given name has no rows.
+    EmptyTable(String),
+    /// The content has fewer lines than the ones skipped by the bounds of the layout.
+    TooShort(usize),
+    /// A column of the layout was not found in the header of its table.
+    ColumnNotFound(ColumnRef),
+    /// A row has no value for a column. Rows are counted from 1 within their table.
+    MissingValue { row: usize, column: ColumnRef },
+    /// A value doesn't match the type of its column.
+    WrongValue { row: usize, column: ColumnRef, value: String },
+    /// The columns of the layout can't be converted into quotes.
+    NotQuotes,
+    /// The notes of the page state units other than the ones expected by the parser, e.g.
+    /// turnover in euros rather than in thousands of euros.
+    WrongUnits(String),
+    /// The row of the index was skipped in [Mode::Lenient], so the records have no index.
+    IndexSkipped(Box<ParseError>),
+    /// The data file couldn't be read.
+    Io(String),
+}
+
+impl ParseError {
+    /// Number of the row that failed, for errors found in a single row.
+    pub fn row(&self) -> Option<usize> {
+        match self {
+            ParseError::MissingValue { row, .. } | ParseError::WrongValue { row, .. } => Some(*row),
+            ParseError::IndexSkipped(x) => x.row(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::TableNotFound(x) => write!(f, "no table {x} found"),
+            ParseError::EmptyTable(x) => write!(f, "the table {x} has no rows"),
+            ParseError::TooShort(x) => write!(f, "only {x} lines found"),
+            ParseError::ColumnNotFound(x) => write!(f, "no column {x} found"),
+            ParseError::MissingValue { row, column } => write!(f, "row {row} has no column {column}"),
+            ParseError::WrongValue { row, column, value } => {
+                write!(f, "row {row} has a wrong value for the column {column}: {value}")
+            },
+            ParseError::NotQuotes => write!(f, "the columns of the layout are not the ones of the quotes"),
+            ParseError::WrongUnits(x) => write!(f, "the page states other units than the expected ones: {x}"),
+            ParseError::IndexSkipped(x) => write!(f, "the row of the index was skipped: {x}"),
+            ParseError::Io(x) => write!(f, "the file couldn't be read: {x}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 /// An object providing a parser for the Ibex index and its associated stocks.
 ///
 /// # Description
@@ -25,6 +88,10 @@ type StockData = Vec<String>;
 /// Just extract the entire HTML div with the `id=root` and save it as a text file.
 /// An example of the data file that results of it is found inside the test folder.
 ///
+/// Despite its name, this parser is not limited to the page of the Ibex 35. BME publishes
+/// the prices of other indices and the Mercado Continuo using the same layout. Use
+/// `with_layout` to parse any of them (see [Layout]).
+///
 /// If the raw text data file has slightly different information, this parser could still
 /// be used. Consider using `with_custom_values` instead of `new` to specify some critical
 /// information about the structure of the text file.
@@ -50,12 +117,29 @@ type StockData = Vec<String>;
 /// }
 /// ```
 /// [ibex35_data]: https://www.bolsasymercados.es/bme-exchange/es/Mercados-y-Cotizaciones/Acciones/Mercado-Continuo/Precios/ibex-35-ES0SI0000005
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct IbexParser {
-    skip_n_lines_beg: usize,
-    ibex_line: usize,
-    skip_n_lines_end: usize,
-    cols_to_keep_main: Vec<usize>,
-    cols_to_keep_stock: Vec<usize>,
+    layout: Layout,
+    mode: Mode,
+}
+
+/// How the parser deals with the rows that can't be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum Mode {
+    /// The data file is rejected on the first row that can't be parsed. Use it when a broken
+    /// data file must be noticed, e.g. in the checks of a pipeline.
+    Strict,
+    /// The rows that can't be parsed are skipped, and the rest of rows are kept. The errors
+    /// of the skipped rows are found in [Records::warnings].
+    #[default]
+    Lenient,
+}
+
+impl Default for IbexParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl IbexParser {
@@ -66,20 +150,43 @@ impl IbexParser {
     /// This constructor builds an instance of IbexParser following the format expected
     /// for a file alike to the one found under `tests/data/data_ibex.csv`.
     ///
-    /// The first 11 lines should be skipped, but the 6th, which contains the information
-    /// for the index itself.
-    /// The last 5 lines contain no useful data either, and are skipped.
+    /// The row that follows the header of the table "Índice" contains the information for
+    /// the index itself, and the rows that follow the header of the table "Nombre" contain
+    /// the information of the stocks. The rest of lines contain no useful data, and are
+    /// skipped.
     ///
     /// Only some columns are parsed: "Nombre", "Fecha", "Hora", "Último", "Volumen" and
     /// "Efectivo (miles €)". For a different parsing schema, use `with_custom_values`.
     pub fn new() -> IbexParser {
-        IbexParser {
-            skip_n_lines_beg: 11,
-            ibex_line: 6,
-            skip_n_lines_end: 5,
-            cols_to_keep_main: vec![0,5,6,1],
-            cols_to_keep_stock: vec![0,7,8,1,5,6],
-        }
+        IbexParser::with_layout(Layout::ibex35())
+    }
+
+    /// Build an instance of IbexParser for a page with the given layout.
+    ///
+    /// # Description
+    ///
+    /// Use this constructor to parse pages other than the one of the Ibex 35, such as the
+    /// pages of the IBEX MEDIUM CAP or the Mercado Continuo. See [Layout::builtin] to get
+    /// the layouts of the pages published by BME.
+    pub fn with_layout(layout: Layout) -> IbexParser {
+        IbexParser { layout, mode: Mode::default() }
+    }
+
+    /// Set how the parser deals with the rows that can't be parsed. Parsers are built in
+    /// [Mode::Lenient].
+    pub fn with_mode(mut self, mode: Mode) -> IbexParser {
+        self.mode = mode;
+        self
+    }
+
+    /// Get how the parser deals with the rows that can't be parsed.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Get the layout used by the parser.
+    pub fn layout(&self) -> &Layout {
+        &self.layout
     }
 
     /// Parameterized constructor for IbexParser.
@@ -96,6 +203,9 @@ impl IbexParser {
     /// It doesn't matter whether there 35 stock rows, or 2, if the bounds of the file are
     /// properly set, and the information is structured in columns split by the character `\t`.
     ///
+    /// The same layout, and more complex ones, can be described in a profile file. See
+    /// [profile][crate::profile] for more details.
+    ///
     /// ## Arguments
     ///
     /// - `inil` indicates the number of header lines that shall be ignored by the parser.
@@ -123,13 +233,13 @@ impl IbexParser {
         colsidx: Vec<usize>,
         colsstock: Vec<usize>
     ) -> IbexParser {
-        IbexParser {
-            skip_n_lines_beg: inil,
-            ibex_line: idxl,
-            skip_n_lines_end: endl,
-            cols_to_keep_main: colsidx,
-            cols_to_keep_stock: colsstock,
-        }
+        IbexParser::with_layout(Layout {
+            name: String::from("custom"),
+            index_columns: colsidx.into_iter().map(Column::from).collect(),
+            stock_columns: colsstock.into_iter().map(Column::from).collect(),
+            bounds: Bounds::Fixed { skip_beg: inil, index_line: idxl, skip_end: endl },
+            ..Layout::ibex35()
+        })
     }
 
     /// Parse a text file that contains stock prices.
@@ -179,47 +289,338 @@ impl IbexParser {
     ///
     /// [ibex35_data]: https://www.bolsasymercados.es/bme-exchange/es/Mercados-y-Cotizaciones/Acciones/Mercado-Continuo/Precios/ibex-35-ES0SI0000005
     pub fn parse_file(&self, path: &Path) -> Option<StockData> {
-        let raw_data = read_to_string(path).expect("Couldn't read lines from the file");
-        let mut counter: usize = 0;
-        let lines: Vec<&str> = raw_data.lines().collect();
-        let end = lines.len() - self.skip_n_lines_end;
-        let mut data: Vec<String> = Vec::with_capacity(N_STOCKS_IN_RAW_FILE);
-        let mut ref_cols_to_keep = &self.cols_to_keep_main;
-
-        if lines.len() < N_LINES_PER_RAW_FILE {
-            None
-        } else {
-
-            for line in lines {
-                if counter == self.ibex_line {
-                    counter += 1;
-                } else if counter < self.skip_n_lines_beg {
-                    counter += 1;
-                    continue;
-                } else if counter < end {
-                    counter += 1;
-                    ref_cols_to_keep = &self.cols_to_keep_stock;
-                } else {
-                    break;
-                }
-
-                let raw_row: Vec<&str> = line.split("\t").collect();
-                let mut row: String = String::from("");
-
-                for col in ref_cols_to_keep.iter() {
-                    row.push_str(raw_row[*col]);
-                    row.push(';');
-                }
-
-                // Remove the last empty space.
-                row.pop();
-                data.push(row);
+        let raw_data = read(path).expect("Couldn't read lines from the file");
+
+        self.parse_bytes(&raw_data)
+    }
+
+    /// Parse the content of a data file that is already loaded in memory.
+    ///
+    /// # Description
+    ///
+    /// This method behaves as `parse_file`, but it receives the raw content of the data file
+    /// rather than its path. It is useful when the data file doesn't live in the file system,
+    /// e.g. when it is stored inside an archive (see [read_entries][crate::archive::read_entries]).
+    ///
+    /// The content is normalised before parsing it, so files with a BOM, `\r\n` line endings
+    /// or Windows-1252 encoding are also accepted. See [decode] for more details.
+    ///
+    /// Content saved as HTML is detected and parsed using `parse_html`.
+    ///
+    /// ## Returns
+    ///
+    /// The same result as `parse_file`.
+    pub fn parse_bytes(&self, data: &[u8]) -> Option<StockData> {
+        self.parse_str(&decode(data))
+    }
+
+    /// Parse the content of a data file into typed quotes.
+    ///
+    /// # Description
+    ///
+    /// This method parses `data` as `parse_bytes` does, and converts the resulting entries
+    /// into typed values (see [Quotes]). The conversion expects the columns selected by the
+    /// built-in layouts, so it fails for layouts that select other columns. It also fails when
+    /// the notes of the page state units other than the expected ones (see `parse_snapshot`).
+    ///
+    /// ## Returns
+    ///
+    /// The quotes of the data file, or `None` if valid data could not be parsed.
+    pub fn parse_quotes(&self, data: &[u8]) -> Option<Quotes> {
+        self.parse_snapshot(data).map(Quotes::from)
+    }
+
+    /// Parse a data file into a snapshot of the market.
+    ///
+    /// # Description
+    ///
+    /// This method parses the file pointed by `path` as `parse_quotes` does, and bundles the
+    /// quotes with the time of the market and the notes found in the page (see [Snapshot]).
+    /// The name of the file is kept as the source of the snapshot, and the time at which it
+    /// was last modified as the capture time.
+    ///
+    /// ## Returns
+    ///
+    /// The snapshot of the data file, or the reason why it couldn't be read or parsed
+    /// ([ParseError::Io] when the file can't be read).
+    pub fn snapshot_file(&self, path: &Path) -> Result<Snapshot, ParseError> {
+        let raw_data = read(path).map_err(|e| ParseError::Io(e.to_string()))?;
+        let mut snapshot = self.try_snapshot(&decode(&raw_data))?;
+
+        snapshot.source = path.file_name().map(|x| x.to_string_lossy().into_owned());
+        snapshot.captured_at = metadata(path).and_then(|x| x.modified()).ok().map(local_time);
+        Ok(snapshot)
+    }
+
+    /// Parse the content of a data file that is already loaded in memory into a snapshot of
+    /// the market.
+    ///
+    /// This method behaves as `snapshot_file`, but the snapshot has no source.
+    pub fn parse_snapshot(&self, data: &[u8]) -> Option<Snapshot> {
+        self.try_snapshot(&decode(data)).ok()
+    }
+
+    /// Parse the content of a data file that is already decoded into a snapshot of the market,
+    /// or tell why it couldn't be parsed.
+    ///
+    /// Besides the errors of `try_with_records`, [ParseError::WrongUnits] is returned when the
+    /// notes of the page state that the prices or the turnover are not expressed in the units
+    /// expected by the parser, as the turnover is converted into euros.
+    pub fn try_snapshot(&self, raw_data: &str) -> Result<Snapshot, ParseError> {
+        let quotes = self.try_with_records(raw_data, Quotes::from_records)?.ok_or(ParseError::NotQuotes)?;
+
+        Snapshot::from_quotes(quotes, raw_data, &self.layout)
+    }
+
+    /// Parse the content of a data file that is already decoded.
+    ///
+    /// # Description
+    ///
+    /// This method behaves as `parse_bytes`, but `raw_data` is expected to be normalised
+    /// text (see [decode]). Content saved as HTML is also detected and parsed using
+    /// `parse_html`.
+    pub fn parse_str(&self, raw_data: &str) -> Option<StockData> {
+        self.with_records(raw_data, |x| x.to_entries())
+    }
+
+    /// Find the records of the content of a data file that is already decoded.
+    ///
+    /// # Description
+    ///
+    /// This method locates the same rows as `parse_str`, but the values of the records are
+    /// slices of `raw_data` rather than new strings (see [Records]). Filtering or converting
+    /// the records makes no allocation per row.
+    ///
+    /// ## Returns
+    ///
+    /// The records of the data file, or `None` if valid data could not be parsed. `None` is
+    /// also returned for content saved as HTML, whose values are not found in `raw_data`
+    /// as is. Use `html_records` or `with_records` for such content.
+    pub fn records<'a>(&self, raw_data: &'a str) -> Option<Records<'a>> {
+        if is_html(raw_data) {
+            return None;
+        }
+
+        self.text_records(raw_data).ok()
+    }
+
+    /// Find the records of the tables extracted from the HTML markup of the page.
+    ///
+    /// # Description
+    ///
+    /// This is the counterpart of `records` for the tables returned by [html::tables]. See
+    /// `parse_html` for more details on how the tables are located.
+    pub fn html_records<'a>(&self, tables: &'a [html::Table]) -> Option<Records<'a>> {
+        self.try_html_records(tables).ok()
+    }
+
+    /// Call `f` with the records of the content of a data file that is already decoded.
+    ///
+    /// # Description
+    ///
+    /// The records borrow their values from the content of the data file, so they can't be
+    /// returned when the content is saved as HTML: the values are rather found in the tables
+    /// extracted from the markup. This method finds the records of either kind of content, and
+    /// hands them over to `f`.
+    ///
+    /// ## Returns
+    ///
+    /// The result of `f`, or `None` if valid data could not be parsed.
+    ///
+    /// # Example of use
+    ///
+    /// ```rust
+    /// use ibex_parser::parser_ibex::IbexParser;
+    /// use std::fs::read_to_string;
+    ///
+    /// let content = read_to_string("./tests/data/data_ibex.html").unwrap();
+    /// let parser = IbexParser::new();
+    /// let names = parser.with_records(&content, |x| x.stocks().filter(|r| r.name().starts_with('A')).count());
+    ///
+    /// assert_eq!(names, Some(7));
+    /// ```
+    pub fn with_records<T>(&self, raw_data: &str, f: impl FnOnce(&Records<'_>) -> T) -> Option<T> {
+        self.try_with_records(raw_data, f).ok()
+    }
+
+    /// Call `f` with the records of the content of a data file, or tell why they couldn't be
+    /// found.
+    ///
+    /// # Description
+    ///
+    /// This method behaves as `with_records`, but it returns the reason why the content
+    /// couldn't be parsed (see [ParseError]) rather than `None`.
+    ///
+    /// # Example of use
+    ///
+    /// ```rust
+    /// use ibex_parser::parser_ibex::{IbexParser, ParseError};
+    ///
+    /// let parser = IbexParser::new();
+    /// let result = parser.try_with_records("Nombre\tÚltimo\n", |x| x.len());
+    ///
+    /// assert_eq!(result, Err(ParseError::TableNotFound(String::from("Índice"))));
+    /// ```
+    pub fn try_with_records<T>(
+        &self,
+        raw_data: &str,
+        f: impl FnOnce(&Records<'_>) -> T
+    ) -> Result<T, ParseError> {
+        if is_html(raw_data) {
+            let tables = html::tables(raw_data);
+            return self.try_html_records(&tables).map(|x| f(&x));
+        }
+
+        self.text_records(raw_data).map(|x| f(&x))
+    }
+
+    fn try_html_records<'a>(&self, tables: &'a [html::Table]) -> Result<Records<'a>, ParseError> {
+        let find = |first_column: &str| {
+            let table = html::find_table(tables, first_column)
+                .ok_or_else(|| ParseError::TableNotFound(String::from(first_column)))?;
+
+            if table.rows.is_empty() {
+                return Err(ParseError::EmptyTable(String::from(first_column)));
             }
 
-            Some(data)
+            Ok(table)
+        };
+
+        let stocks = find(&self.layout.stock_header)?;
+        let index = match &self.layout.index_header {
+            Some(first_column) => {
+                let index = find(first_column)?;
+                let rows = vec![Cells::Html(&index.rows[0])];
+                Some(Table::new(Some(&index.header), rows, &self.layout.index_columns, self.mode)?)
+            },
+            None => None,
+        };
+        let rows = stocks.rows.iter().map(|x| Cells::Html(x)).collect();
+
+        Records::new(index, Table::new(Some(&stocks.header), rows, &self.layout.stock_columns, self.mode)?)
+    }
+
+    /// Find the records of a text file.
+    fn text_records<'a>(&self, raw_data: &'a str) -> Result<Records<'a>, ParseError> {
+        match self.layout.bounds {
+            Bounds::Markers => self.text_records_markers(raw_data),
+            Bounds::Fixed { skip_beg, index_line, skip_end } => {
+                self.text_records_fixed(raw_data, skip_beg, index_line, skip_end)
+            },
         }
     }
 
+    /// Find the records of a text file whose rows are located using the headers of the tables.
+    fn text_records_markers<'a>(&self, raw_data: &'a str) -> Result<Records<'a>, ParseError> {
+        let lines: Vec<&str> = raw_data.lines().collect();
+
+        let index = match &self.layout.index_header {
+            Some(first_column) => {
+                let (header, rows) = self.find_table(&lines, first_column)?;
+                let rows = vec![Cells::Line(rows[0])];
+                Some(Table::new(Some(&header), rows, &self.layout.index_columns, self.mode)?)
+            },
+            None => None,
+        };
+
+        let (header, rows) = self.find_table(&lines, &self.layout.stock_header)?;
+        let rows = rows.into_iter().map(Cells::Line).collect();
+
+        Records::new(index, Table::new(Some(&header), rows, &self.layout.stock_columns, self.mode)?)
+    }
+
+    /// Find the header and the rows of the table whose first column is named `first_column`.
+    ///
+    /// The rows of a table end with the first empty line, the footer of the page, or the
+    /// header of a table. Rows with fewer columns than needed by the layout are then reported
+    /// as [ParseError::MissingValue] in [Mode::Strict], rather than ending the table. In
+    /// [Mode::Lenient], the rows also end with the first line that is not split into columns
+    /// (e.g. a title or a note). An error is returned when the table is not found, or when it
+    /// has no rows.
+    fn find_table<'a>(
+        &self,
+        lines: &[&'a str],
+        first_column: &str
+    ) -> Result<(Vec<&'a str>, Vec<&'a str>), ParseError> {
+        let mut lines = lines.iter();
+        let header: Vec<&str> = lines
+            .by_ref()
+            .map(|x| x.split('\t').collect::<Vec<&str>>())
+            .find(|x| x.len() > 1 && x[0] == first_column)
+            .ok_or_else(|| ParseError::TableNotFound(String::from(first_column)))?;
+        let is_header = |x: &str| {
+            let first = x.split('\t').next().unwrap_or_default();
+            first == self.layout.stock_header || self.layout.index_header.as_deref() == Some(first)
+        };
+        let is_row = |x: &str| match self.mode {
+            Mode::Strict => !x.trim().is_empty(),
+            Mode::Lenient => x.contains('\t'),
+        };
+        let rows: Vec<&str> = lines
+            .take_while(|x| is_row(x) && !is_header(x) && !x.starts_with(&self.layout.footer))
+            .copied()
+            .collect();
+
+        if rows.is_empty() {
+            return Err(ParseError::EmptyTable(String::from(first_column)));
+        }
+
+        Ok((header, rows))
+    }
+
+    /// Find the records of a text file whose rows are located by their position.
+    fn text_records_fixed<'a>(
+        &self,
+        raw_data: &'a str,
+        skip_beg: usize,
+        index_line: usize,
+        skip_end: usize
+    ) -> Result<Records<'a>, ParseError> {
+        let lines: Vec<&str> = raw_data.lines().collect();
+
+        if lines.len() <= skip_beg + skip_end || index_line >= lines.len() {
+            return Err(ParseError::TooShort(lines.len()));
+        }
+
+        // The header of a table is expected in the line before its first row.
+        let header = |x: usize| x.checked_sub(1).map(|x| lines[x].split('\t').collect::<Vec<&str>>());
+        let end = lines.len() - skip_end;
+
+        let index = vec![Cells::Line(lines[index_line])];
+        let rows: Vec<Cells> = (skip_beg..end)
+            .filter(|x| *x != index_line)
+            .map(|x| Cells::Line(lines[x]))
+            .collect();
+
+        let index = Table::new(header(index_line).as_deref(), index, &self.layout.index_columns, self.mode)?;
+        let stocks = Table::new(header(skip_beg).as_deref(), rows, &self.layout.stock_columns, self.mode)?;
+
+        Records::new(Some(index), stocks)
+    }
+
+    /// Parse the HTML markup of the page that contains stock prices.
+    ///
+    /// # Description
+    ///
+    /// This method extracts the same information as `parse_file` from the HTML markup of
+    /// [BME's web page][ibex35_data], i.e. the content of the element `div#root` of the page.
+    /// The tables of the page are identified by the name of their first column, as given by
+    /// the layout of the parser: "Índice" for the index, and "Nombre" for the stocks. Hence,
+    /// the bounds given to `with_custom_values` are ignored, though the selection of columns
+    /// is not.
+    ///
+    /// ## Returns
+    ///
+    /// The same result as `parse_file`. `None` is returned when any of the tables is not
+    /// found, or when it has no rows.
+    ///
+    /// [ibex35_data]: https://www.bolsasymercados.es/bme-exchange/es/Mercados-y-Cotizaciones/Acciones/Mercado-Continuo/Precios/ibex-35-ES0SI0000005
+    pub fn parse_html(&self, raw_data: &str) -> Option<StockData> {
+        let tables = html::tables(raw_data);
+
+        Some(self.html_records(&tables)?.to_entries())
+    }
+
     /// Parse and filter a text file that contains stock prices.
     ///
     /// # Description
@@ -256,38 +657,35 @@ impl IbexParser {
     ///
     /// [ibex35_data]: https://www.bolsasymercados.es/bme-exchange/es/Mercados-y-Cotizaciones/Acciones/Mercado-Continuo/Precios/ibex-35-ES0SI0000005
     pub fn filter_file(&self, path: &Path, filter: &StockFilter) -> Option<StockData> {
-        let raw_data = self.parse_file(path);
-
-        if raw_data == None {
-            return None;
-        }
+        let raw_data = read(path).expect("Couldn't read lines from the file");
 
-        // Allow using this method as a regular `parse_file` when no filters are given.
-        if filter.len() == 0 {
-            return raw_data;
-        }
-
-        let mut data: StockData = Vec::new();
-
-        for item in raw_data.unwrap().iter() {
-            for f in filter {
-                if item.contains(f) {
-                    data.push(item.clone());
-                    break;
-                }
-            }
-        }
+        self.filter_bytes(&raw_data, filter)
+    }
 
-        Some(data)
+    /// Parse and filter the content of a data file that is already loaded in memory.
+    ///
+    /// # Description
+    ///
+    /// This method is the counterpart of `filter_file` for data that doesn't live in the file
+    /// system. See `parse_bytes` for more details.
+    pub fn filter_bytes(&self, data: &[u8], filter: &StockFilter) -> Option<StockData> {
+        // Only the entries that match the filter are built.
+        self.with_records(&decode(data), |x| x.filter(filter).map(|x| x.to_string()).collect())
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_allocation, clippy::boxed_local)]
 mod tests {
     use super::*;
     use rstest::*;
     use pretty_assertions::assert_eq;
+    use std::fs::read_to_string;
     use std::path::Path;
+    use crate::layout::FieldKind;
+
+    /// How many stock prices are included in a raw text file.
+    const N_STOCKS_IN_RAW_FILE: usize = 36;
 
     #[fixture]
     fn valid_data() -> Box<&'static Path> {
@@ -336,6 +734,192 @@ mod tests {
         let _parsed_data = parser.parse_file(path).unwrap();
     }
 
+    // Check that a snapshot of a non existing file is an error rather than a panic.
+    #[rstest]
+    fn test_ibexparser_snapshot_nofile(non_existing_data: Box<&'static Path>) {
+        let result = IbexParser::new().snapshot_file(*non_existing_data);
+
+        assert!(matches!(result, Err(ParseError::Io(_))));
+    }
+
+    // Check that parsing the content of a file yields the same result as parsing the file.
+    #[rstest]
+    fn test_ibexparser_parse_bytes(valid_data: Box<&'static Path>) {
+        let parser = IbexParser::new();
+        let path = *valid_data;
+        let raw_data = read(path).unwrap();
+
+        assert_eq!(parser.parse_bytes(&raw_data), parser.parse_file(path));
+        assert_eq!(parser.parse_bytes(&[0xff, 0xfe, 0x00]), None);
+    }
+
+    // Check that the records of a file yield the same entries as parsing the file.
+    #[rstest]
+    fn test_ibexparser_records(valid_data: Box<&'static Path>) {
+        let parser = IbexParser::new();
+        let content = decode(&read(*valid_data).unwrap());
+        let records = parser.records(&content).unwrap();
+
+        assert_eq!(Some(records.to_entries()), parser.parse_file(*valid_data));
+        assert_eq!(records.len(), N_STOCKS_IN_RAW_FILE);
+        assert_eq!(records.stocks().filter(|x| x.matches(&["ACS"])).count(), 1);
+
+        let html = read_to_string("./tests/data/data_ibex.html").unwrap();
+        assert!(parser.records(&html).is_none());
+        assert_eq!(parser.with_records(&html, |x| x.to_entries()), Some(records.to_entries()));
+    }
+
+    // Check that the reason why a file is rejected is given.
+    #[rstest]
+    #[case(IbexParser::new(), "./tests/data/layouts/data_mercado_continuo.csv", "no table Índice found")]
+    #[case(IbexParser::with_custom_values(90, 6, 5, vec![0], vec![0]), "./tests/data/data_ibex.csv", "only 51 lines found")]
+    #[case(IbexParser::with_custom_values(11, 6, 5, vec![0], vec![12]), "./tests/data/data_ibex.csv", "row 1 has no column #12")]
+    fn test_ibexparser_parse_error(#[case] parser: IbexParser, #[case] path: &str, #[case] expected: &str) {
+        let content = decode(&read(path).unwrap());
+        let error = parser.try_with_records(&content, |x| x.len()).unwrap_err();
+
+        assert_eq!(error.to_string(), expected);
+    }
+
+    // Check that broken rows are skipped in lenient mode, the default, and reject the file in
+    // strict mode.
+    #[rstest]
+    fn test_ibexparser_parse_lenient(valid_data: Box<&'static Path>) {
+        let content = decode(&read(*valid_data).unwrap());
+        // A row with a wrong time, and a row cut in the middle.
+        let content = content
+            .replacen("06/02/2024\tCierre", "06/02/2024\t-", 1)
+            .replacen("\t1.945.142\t20.304,76\t06/02/2024\tCierre", "", 1);
+
+        let parser = IbexParser::new().with_mode(Mode::Strict);
+        let error = parser.try_with_records(&content, |x| x.len()).unwrap_err();
+        assert_eq!(error, ParseError::WrongValue {
+            row: 1,
+            column: ColumnRef::Name(String::from("Hora")),
+            value: String::from("-"),
+        });
+
+        let parser = IbexParser::new();
+        let (entries, warnings) = parser
+            .try_with_records(&content, |x| (x.to_entries(), x.warnings().count()))
+            .unwrap();
+        assert_eq!((entries.len(), warnings), (N_STOCKS_IN_RAW_FILE - 2, 2));
+        assert_eq!(entries[1], "ACCIONA ENER;06/02/2024;Cierre;23,2600;599.888;13.817,09");
+        assert_eq!(entries.last().unwrap(), "UNICAJA;06/02/2024;Cierre;0,9345;17.621.854;16.331,86");
+    }
+
+    // Check that a row cut in the middle of the table is reported in strict mode, rather than
+    // ending the table.
+    #[rstest]
+    fn test_ibexparser_parse_strict(valid_data: Box<&'static Path>) {
+        let content = decode(&read(*valid_data).unwrap());
+        let content = content.replacen("\t1.945.142\t20.304,76\t06/02/2024\tCierre", "", 1);
+
+        let parser = IbexParser::new().with_mode(Mode::Strict);
+        let error = parser.try_with_records(&content, |x| x.len()).unwrap_err();
+        assert_eq!(error, ParseError::MissingValue { row: 3, column: ColumnRef::Name(String::from("Fecha")) });
+    }
+
+    // Check that the rows of a table end with the table in lenient mode, even when no empty
+    // line follows it, and that a broken row of the index is reported.
+    #[rstest]
+    fn test_ibexparser_parse_lenient_bounds(valid_data: Box<&'static Path>) {
+        let content = decode(&read(*valid_data).unwrap());
+        let content = content
+            .replacen("\n\nInformación diferida", "\nPrecios de cierre.\nInformación diferida", 1)
+            .replacen("06/02/2024\t17:37:03", "06/02/2024\t-", 1);
+
+        let (n_records, index, warnings) = IbexParser::new()
+            .try_with_records(&content, |x| {
+                (x.len(), x.index().is_some(), x.warnings().map(|x| x.to_string()).collect::<Vec<String>>())
+            })
+            .unwrap();
+        assert_eq!((n_records, index), (N_STOCKS_IN_RAW_FILE - 1, false));
+        assert_eq!(warnings, vec!["the row of the index was skipped: row 1 has a wrong value for the column Hora: -"]);
+    }
+
+    // Check that the HTML markup of the page yields the same result as its text version.
+    #[rstest]
+    fn test_ibexparser_parse_htmlfile(valid_data: Box<&'static Path>) {
+        let parser = IbexParser::new();
+        let path = Path::new("./tests/data/data_ibex.html");
+
+        assert_eq!(parser.parse_file(path), parser.parse_file(*valid_data));
+
+        let custom_parser = IbexParser::with_custom_values(11, 6, 5, vec![0,1], vec![0,1]);
+        let parsed_data = custom_parser.parse_file(path).unwrap();
+        assert_eq!(parsed_data[0], "IBEX 35®;10.003,00");
+        assert_eq!(parsed_data[1], "ACCIONA;114,4500");
+    }
+
+    #[rstest]
+    fn test_ibexparser_parse_wronghtml() {
+        let parser = IbexParser::new();
+        let html = "<div id=\"root\"><table><tr><th>Índice</th></tr></table></div>";
+
+        assert_eq!(parser.parse_html(html), None);
+    }
+
+    // Check that named columns are found when the rows are located by their position.
+    #[rstest]
+    fn test_ibexparser_parse_fixednames(valid_data: Box<&'static Path>) {
+        let layout = Layout {
+            bounds: Bounds::Fixed { skip_beg: 11, index_line: 6, skip_end: 5 },
+            ..Layout::ibex35()
+        };
+        let parser = IbexParser::with_layout(layout);
+
+        assert_eq!(parser.parse_file(*valid_data), IbexParser::new().parse_file(*valid_data));
+    }
+
+    // Check that a file whose values don't match the type of their columns is rejected.
+    #[rstest]
+    fn test_ibexparser_parse_wrongtype(valid_data: Box<&'static Path>) {
+        let mut layout = Layout::ibex35();
+        layout.stock_columns[3].kind = FieldKind::Date;
+        let parser = IbexParser::with_layout(layout);
+
+        assert_eq!(parser.parse_file(*valid_data), None);
+    }
+
+    // Check that pages with other layouts can be parsed, whatever their number of stocks.
+    #[rstest]
+    #[case("data_ibex_medium_cap.csv", "ibex-medium-cap", 21)]
+    #[case("data_ibex35_bancos.csv", "ibex35-sector", 7)]
+    #[case("data_mercado_continuo.csv", "mercado-continuo", 151)]
+    fn test_ibexparser_parse_layouts(
+        #[case] file: &str,
+        #[case] layout: &str,
+        #[case] expected: usize
+    ) {
+        let parser = IbexParser::with_layout(Layout::builtin(layout).unwrap());
+        let path = format!("./tests/data/layouts/{file}");
+
+        let parsed_data = parser.parse_file(Path::new(&path)).unwrap();
+        assert_eq!(parsed_data.len(), expected);
+        assert!(parsed_data.iter().skip(1).all(|x| x.split(';').count() == 6));
+    }
+
+    // Check that a page without the table of the index is not parsed as the Ibex 35.
+    #[rstest]
+    fn test_ibexparser_parse_wronglayout() {
+        let parser = IbexParser::new();
+        let path = Path::new("./tests/data/layouts/data_mercado_continuo.csv");
+
+        assert_eq!(parser.parse_file(path), None);
+    }
+
+    // Check that a file saved on Windows (CRLF and Windows-1252) yields the same result.
+    #[rstest]
+    fn test_ibexparser_parse_windowsfile(valid_data: Box<&'static Path>) {
+        let parser = IbexParser::new();
+        let path = Path::new("./tests/data/data_ibex_win.csv");
+
+        let parsed_data = parser.parse_file(path).unwrap();
+        assert_eq!(parsed_data[0], "IBEX 35®;06/02/2024;17:37:03;10.003,00");
+        assert_eq!(Some(parsed_data), parser.parse_file(*valid_data));
+    }
+
     #[rstest]
     fn test_ibexparser_parse_wrongfile(wrong_data: Box<&'static Path>) {
         let parser = IbexParser::new();
@@ -392,4 +976,16 @@ mod tests {
         assert_eq!(parsed_data, None);
     }
 
-}
\ No newline at end of file
+    #[cfg(feature = "serde")]
+    #[rstest]
+    fn test_ibexparser_serde() {
+        let parser = IbexParser::with_layout(Layout::mercado_continuo()).with_mode(Mode::Lenient);
+        let value = serde_json::to_value(&parser).unwrap();
+
+        assert_eq!(value["layout"]["name"], "mercado-continuo");
+        assert_eq!(value["mode"], "lenient");
+
+        let parser: IbexParser = serde_json::from_value(value).unwrap();
+        assert_eq!((parser.layout(), parser.mode()), (&Layout::mercado_continuo(), Mode::Lenient));
+    }
+}
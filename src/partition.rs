@@ -0,0 +1,271 @@
+// Copyright 2024 Felipe Torres González
+
+//! Output of the parsed data split into files.
+//!
+//! Rather than a single output with the entries of all the data files, the entries can be
+//! written into a directory with a file per stock, per session, or per stock and session.
+//! Downstream tools can then load one instrument at a time.
+//!
+//! Partitions are appended to, so the same directory can receive the output of several
+//! runs. Entries that are already found in a partition are not written again, so parsing
+//! the same data file twice doesn't duplicate its entries.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use crate::layout::Layout;
+use crate::quote::parse_date;
+use crate::record::OwnedRecord;
+
+/// How the entries are split into files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PartitionBy {
+    /// A file per stock, e.g. `AENA.csv`.
+    Ticker,
+    /// A file per session, e.g. `2024-02-06.csv`.
+    Date,
+    /// A directory per stock, with a file per session, e.g. `AENA/2024-02-06.csv`.
+    #[value(name = "ticker,date")]
+    TickerDate,
+}
+
+impl fmt::Display for PartitionBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionBy::Ticker => write!(f, "ticker"),
+            PartitionBy::Date => write!(f, "date"),
+            PartitionBy::TickerDate => write!(f, "ticker,date"),
+        }
+    }
+}
+
+/// Writer of the entries returned by the parser into partitions.
+///
+/// # Example of use
+///
+/// ```rust,no_run
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use ibex_parser::partition::{PartitionBy, PartitionWriter};
+/// use std::fs::read_to_string;
+/// use std::path::Path;
+///
+/// let content = read_to_string("./tests/data/data_ibex.csv").unwrap();
+/// let parser = IbexParser::new();
+/// let records: Vec<_> = parser.records(&content).unwrap().iter().map(|x| x.to_owned_record()).collect();
+/// let mut writer = PartitionWriter::new(Path::new("out"), PartitionBy::TickerDate);
+///
+/// // Entries of AENA are written to out/AENA/2024-02-06.csv
+/// writer.write(&records, parser.layout()).unwrap();
+/// ```
+pub struct PartitionWriter {
+    dir: PathBuf,
+    by: PartitionBy,
+    // Entries found in each partition that was written during this run.
+    written: HashMap<PathBuf, HashSet<String>>,
+}
+
+/// Make a name safe to be used as a file name.
+///
+/// Only letters, digits, spaces, `-`, `_` and `.` are kept, any other character is replaced
+/// by `_`.
+fn file_name(name: &str) -> String {
+    // Names such as ".." would point outside the directory.
+    if name.chars().all(|x| x == '.') {
+        return "_".repeat(name.chars().count().max(1));
+    }
+
+    name.chars()
+        .map(|x| if x.is_alphanumeric() || matches!(x, ' ' | '-' | '_' | '.') { x } else { '_' })
+        .collect()
+}
+
+impl PartitionWriter {
+    /// Build a writer into a directory, which is created when the first entry is written.
+    pub fn new(dir: &Path, by: PartitionBy) -> PartitionWriter {
+        PartitionWriter { dir: dir.to_path_buf(), by, written: HashMap::new() }
+    }
+
+    /// Path of the partition for the entries of a stock in a session.
+    ///
+    /// `date` is given with the format of the entries (`DD/MM/YYYY`). `None` is returned when
+    /// it is not a valid date.
+    pub fn partition(&self, ticker: &str, date: &str) -> Option<PathBuf> {
+        let date = parse_date(date)?.format("%Y-%m-%d").to_string();
+
+        Some(match self.by {
+            PartitionBy::Ticker => self.dir.join(format!("{}.csv", file_name(ticker))),
+            PartitionBy::Date => self.dir.join(format!("{date}.csv")),
+            PartitionBy::TickerDate => self.dir.join(file_name(ticker)).join(format!("{date}.csv")),
+        })
+    }
+
+    /// Entries found in a partition, read from the file the first time.
+    fn entries(&mut self, path: &Path) -> io::Result<&mut HashSet<String>> {
+        if !self.written.contains_key(path) {
+            let entries = match fs::read_to_string(path) {
+                Ok(x) => x.lines().map(String::from).collect(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+                Err(e) => return Err(e),
+            };
+            self.written.insert(path.to_path_buf(), entries);
+        }
+
+        Ok(self.written.get_mut(path).unwrap())
+    }
+
+    /// Append the records of a data file to their partitions.
+    ///
+    /// # Description
+    ///
+    /// Each record goes to the partition given by its name (the first value) and its date,
+    /// which is found using the columns of `layout` (see [Layout::date_column]). Records that
+    /// are already found in their partition are skipped.
+    ///
+    /// ## Return
+    ///
+    /// The number of records that were written. An error of kind `InvalidData` is returned,
+    /// and nothing is written, when any of the records has no valid date.
+    pub fn write(&mut self, records: &[OwnedRecord], layout: &Layout) -> io::Result<usize> {
+        let mut partitions: Vec<(PathBuf, Vec<String>)> = Vec::new();
+
+        for record in records {
+            let entry = record.to_string();
+            let date = layout.date_column(record.kind).and_then(|x| record.fields.get(x));
+            let path = date
+                .and_then(|x| self.partition(record.fields.first().map_or("", String::as_str), x))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("entry with no valid date: {entry}"))
+                })?;
+
+            match partitions.iter_mut().find(|(x, _)| *x == path) {
+                Some((_, x)) => x.push(entry),
+                None => partitions.push((path, vec![entry])),
+            }
+        }
+
+        let mut count = 0;
+
+        for (path, entries) in partitions {
+            let known = self.entries(&path)?;
+            let new: Vec<String> = entries.into_iter().filter(|x| known.insert(x.clone())).collect();
+
+            if new.is_empty() {
+                continue;
+            }
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            for entry in new.iter() {
+                writeln!(file, "{entry}")?;
+            }
+            count += new.len();
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use crate::testing::*;
+    use crate::layout::{Column, FieldKind};
+    use crate::record::RecordKind;
+
+    const ENTRIES: [&str; 3] = [
+        "AENA;06/02/2024;15:33:14;170,9500;49.714;8.394,78",
+        "AENA;07/02/2024;15:40:08;171,0500;51.364;8.676,92",
+        "ACS;06/02/2024;Cierre;36,5000;501.552;18.341,96",
+    ];
+
+    fn records(entries: &[&str]) -> Vec<OwnedRecord> {
+        entries
+            .iter()
+            .map(|x| OwnedRecord { kind: RecordKind::Stock, fields: x.split(';').map(String::from).collect() })
+            .collect()
+    }
+
+    #[rstest]
+    #[case(PartitionBy::Ticker, "AENA.csv")]
+    #[case(PartitionBy::Date, "2024-02-06.csv")]
+    #[case(PartitionBy::TickerDate, "AENA/2024-02-06.csv")]
+    fn test_partition(#[case] by: PartitionBy, #[case] expected: &str) {
+        let writer = PartitionWriter::new(Path::new("out"), by);
+
+        assert_eq!(writer.partition("AENA", "06/02/2024").unwrap(), Path::new("out").join(expected));
+        assert_eq!(writer.partition("AENA", "Cierre"), None);
+        assert_eq!(PartitionBy::from_str(&by.to_string(), false).unwrap(), by);
+    }
+
+    #[rstest]
+    #[case("A/B", "A_B")]
+    #[case("..", "__")]
+    #[case("A:B*?\"<>|\\", "A_B_______")]
+    #[case("IBEX 35®", "IBEX 35_")]
+    #[case("ACCIONA ENER", "ACCIONA ENER")]
+    #[case("A.B\nC", "A.B_C")]
+    fn test_partition_file_name(#[case] ticker: &str, #[case] expected: &str) {
+        let writer = PartitionWriter::new(Path::new("out"), PartitionBy::Ticker);
+
+        assert_eq!(writer.partition(ticker, "06/02/2024").unwrap(), Path::new("out").join(format!("{expected}.csv")));
+    }
+
+    #[rstest]
+    fn test_partition_write(tmp: TempDir) {
+        let layout = Layout::ibex35();
+        let mut writer = PartitionWriter::new(&tmp.0, PartitionBy::TickerDate);
+
+        assert_eq!(writer.write(&records(&ENTRIES), &layout).unwrap(), 3);
+        assert_eq!(
+            fs::read_to_string(tmp.0.join("AENA/2024-02-07.csv")).unwrap(),
+            format!("{}\n", ENTRIES[1])
+        );
+
+        // Entries are not duplicated, neither in the same run nor in a later run.
+        assert_eq!(writer.write(&records(&ENTRIES[..1]), &layout).unwrap(), 0);
+        let mut writer = PartitionWriter::new(&tmp.0, PartitionBy::TickerDate);
+        assert_eq!(writer.write(&records(&ENTRIES), &layout).unwrap(), 0);
+
+        let new = "AENA;06/02/2024;15:44:54;171,3000;52.498;8.871,12";
+        assert_eq!(writer.write(&records(&[new]), &layout).unwrap(), 1);
+        assert_eq!(
+            fs::read_to_string(tmp.0.join("AENA/2024-02-06.csv")).unwrap(),
+            format!("{}\n{new}\n", ENTRIES[0])
+        );
+    }
+
+    #[rstest]
+    fn test_partition_write_date_column(tmp: TempDir) {
+        // The date is the last of the parsed columns.
+        let layout = Layout {
+            stock_columns: vec![
+                Column::named("Nombre", FieldKind::Text),
+                Column::named("Último", FieldKind::Decimal),
+                Column::named("Fecha", FieldKind::Date),
+            ],
+            ..Layout::ibex35()
+        };
+        let mut writer = PartitionWriter::new(&tmp.0, PartitionBy::Date);
+
+        assert_eq!(writer.write(&records(&["AENA;170,9500;06/02/2024"]), &layout).unwrap(), 1);
+        assert!(tmp.0.join("2024-02-06.csv").exists());
+    }
+
+    #[rstest]
+    fn test_partition_write_wrong(tmp: TempDir) {
+        let mut writer = PartitionWriter::new(&tmp.0, PartitionBy::Date);
+        let result = writer.write(&records(&[ENTRIES[0], "AENA;Cierre;170,9500"]), &Layout::ibex35());
+
+        // No record of the data file is written.
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(!tmp.0.join("2024-02-06.csv").exists());
+    }
+}
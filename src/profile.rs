@@ -0,0 +1,485 @@
+// Copyright 2024 Felipe Torres González
+
+//! Layout profiles loaded from TOML or JSON files.
+//!
+//! A profile file describes the [Layout] of a page in a declarative way, so the parser can
+//! be adapted to a redesign of BME's web page without recompiling the tool.
+//!
+//! # Format
+//!
+//! A profile starts from a built-in layout (`base`, `ibex35` by default) and overrides the
+//! values that are given in the file. All the keys are optional:
+//!
+//! ```toml
+//! name = "ibex35-2023"
+//! base = "ibex35"
+//! title = "IBEX 35"
+//! # Whether the title is only the first words of the title of the page.
+//! title_prefix = false
+//! footer = "Información diferida"
+//!
+//! # Use `index = false` for pages with no table for an index.
+//! [index]
+//! header = "Índice"
+//! columns = ["Índice", "Fecha", "Hora", "Último"]
+//!
+//! [stocks]
+//! header = "Nombre"
+//! columns = [
+//!     "Nombre",
+//!     { name = "Fecha", type = "date" },
+//!     { name = "Hora", type = "time" },
+//!     { index = 1, type = "decimal" },
+//! ]
+//!
+//! # Locate the rows by their position rather than by the headers of the tables.
+//! [lines]
+//! skip_beg = 11
+//! index_line = 6
+//! skip_end = 5
+//! ```
+//!
+//! Columns are given by their name in the header of the table, by their position (starting
+//! from 0), or by a table with the key `name` or `index` and the key `type`. The available
+//! types are: `text` (default), `integer`, `decimal`, `percent`, `date` and `time` (see
+//! [FieldKind]). The same keys are used in JSON files.
+
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::Path;
+use serde_json::{Map, Value};
+use crate::layout::{Bounds, Column, ColumnRef, FieldKind, Layout};
+
+/// Errors found when loading a profile.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// The profile file couldn't be read.
+    Io(std::io::Error),
+    /// The file has a format other than TOML (`.toml`) or JSON (`.json`).
+    UnknownFormat,
+    /// The content of a TOML file is not valid.
+    Toml(toml::de::Error),
+    /// The content of a JSON file is not valid.
+    Json(serde_json::Error),
+    /// The profile is well formed, but its values are not valid.
+    Invalid(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "Couldn't read the profile: {e}"),
+            ProfileError::UnknownFormat => write!(f, "Profiles must be TOML or JSON files"),
+            ProfileError::Toml(e) => write!(f, "Wrong TOML profile: {e}"),
+            ProfileError::Json(e) => write!(f, "Wrong JSON profile: {e}"),
+            ProfileError::Invalid(e) => write!(f, "Wrong profile: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+struct ProfileFile {
+    name: Option<String>,
+    base: Option<String>,
+    title: Option<String>,
+    title_prefix: Option<bool>,
+    footer: Option<String>,
+    index: Option<IndexSpec>,
+    stocks: Option<TableSpec>,
+    lines: Option<LinesSpec>,
+}
+
+enum IndexSpec {
+    Enabled(bool),
+    Table(TableSpec),
+}
+
+struct TableSpec {
+    header: Option<String>,
+    columns: Option<Vec<ColumnSpec>>,
+}
+
+enum ColumnSpec {
+    Index(usize),
+    Name(String),
+    Full {
+        name: Option<String>,
+        index: Option<usize>,
+        kind: KindSpec,
+    },
+}
+
+#[derive(Default, Clone, Copy)]
+enum KindSpec {
+    #[default]
+    Text,
+    Integer,
+    Decimal,
+    Percent,
+    Date,
+    Time,
+}
+
+struct LinesSpec {
+    skip_beg: usize,
+    index_line: usize,
+    skip_end: usize,
+}
+
+fn invalid(message: String) -> ProfileError {
+    ProfileError::Invalid(message)
+}
+
+/// Get a table, checking that it has no keys other than the given ones.
+fn table<'a>(value: &'a Value, name: &str, keys: &[&str]) -> Result<&'a Map<String, Value>, ProfileError> {
+    let table = value.as_object().ok_or_else(|| invalid(format!("{name} must be a table")))?;
+
+    match table.keys().find(|x| !keys.contains(&x.as_str())) {
+        Some(x) => Err(invalid(format!("unknown key {x} in {name}"))),
+        None => Ok(table),
+    }
+}
+
+/// Get an optional value of a table, converted with `convert`.
+fn get<'a, T>(
+    table: &'a Map<String, Value>,
+    key: &str,
+    expected: &str,
+    convert: impl Fn(&'a Value) -> Option<T>,
+) -> Result<Option<T>, ProfileError> {
+    table
+        .get(key)
+        .map(|x| convert(x).ok_or_else(|| invalid(format!("{key} must be {expected}"))))
+        .transpose()
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(String::from)
+}
+
+fn number(value: &Value) -> Option<usize> {
+    value.as_u64()?.try_into().ok()
+}
+
+impl ProfileFile {
+    fn from_value(value: &Value) -> Result<ProfileFile, ProfileError> {
+        let keys = ["name", "base", "title", "title_prefix", "footer", "index", "stocks", "lines"];
+        let file = table(value, "the profile", &keys)?;
+
+        Ok(ProfileFile {
+            name: get(file, "name", "a string", string)?,
+            base: get(file, "base", "a string", string)?,
+            title: get(file, "title", "a string", string)?,
+            title_prefix: get(file, "title_prefix", "a boolean", Value::as_bool)?,
+            footer: get(file, "footer", "a string", string)?,
+            index: file.get("index").map(IndexSpec::from_value).transpose()?,
+            stocks: file.get("stocks").map(|x| TableSpec::from_value(x, "stocks")).transpose()?,
+            lines: file.get("lines").map(LinesSpec::from_value).transpose()?,
+        })
+    }
+}
+
+impl IndexSpec {
+    fn from_value(value: &Value) -> Result<IndexSpec, ProfileError> {
+        match value {
+            Value::Bool(x) => Ok(IndexSpec::Enabled(*x)),
+            x => Ok(IndexSpec::Table(TableSpec::from_value(x, "index")?)),
+        }
+    }
+}
+
+impl TableSpec {
+    fn from_value(value: &Value, name: &str) -> Result<TableSpec, ProfileError> {
+        let spec = table(value, name, &["header", "columns"])?;
+        let columns = get(spec, "columns", "an array", Value::as_array)?
+            .map(|x| x.iter().map(ColumnSpec::from_value).collect::<Result<Vec<_>, _>>())
+            .transpose()?;
+
+        Ok(TableSpec { header: get(spec, "header", "a string", string)?, columns })
+    }
+}
+
+impl ColumnSpec {
+    fn from_value(value: &Value) -> Result<ColumnSpec, ProfileError> {
+        match value {
+            Value::Number(_) => number(value)
+                .map(ColumnSpec::Index)
+                .ok_or_else(|| invalid(format!("wrong index of a column {value}"))),
+            Value::String(x) => Ok(ColumnSpec::Name(x.clone())),
+            _ => {
+                let spec = table(value, "a column", &["name", "index", "type"])?;
+
+                Ok(ColumnSpec::Full {
+                    name: get(spec, "name", "a string", string)?,
+                    index: get(spec, "index", "a positive integer", number)?,
+                    kind: get(spec, "type", "a type of column", KindSpec::from_value)?.unwrap_or_default(),
+                })
+            },
+        }
+    }
+
+    fn into_column(self) -> Result<Column, ProfileError> {
+        match self {
+            ColumnSpec::Index(x) => Ok(Column::from(x)),
+            ColumnSpec::Name(x) => Ok(Column::named(&x, FieldKind::Text)),
+            ColumnSpec::Full { name: Some(x), index: None, kind } => {
+                Ok(Column::named(&x, kind.into()))
+            },
+            ColumnSpec::Full { name: None, index: Some(x), kind } => {
+                Ok(Column { source: ColumnRef::Index(x), kind: kind.into() })
+            },
+            ColumnSpec::Full { .. } => Err(ProfileError::Invalid(
+                String::from("a column needs either a name or an index")
+            )),
+        }
+    }
+}
+
+impl KindSpec {
+    fn from_value(value: &Value) -> Option<KindSpec> {
+        match value.as_str()? {
+            "text" => Some(KindSpec::Text),
+            "integer" => Some(KindSpec::Integer),
+            "decimal" => Some(KindSpec::Decimal),
+            "percent" => Some(KindSpec::Percent),
+            "date" => Some(KindSpec::Date),
+            "time" => Some(KindSpec::Time),
+            _ => None,
+        }
+    }
+}
+
+impl LinesSpec {
+    fn from_value(value: &Value) -> Result<LinesSpec, ProfileError> {
+        let lines = table(value, "lines", &["skip_beg", "index_line", "skip_end"])?;
+        let line = |key| {
+            get(lines, key, "a positive integer", number)?.ok_or_else(|| invalid(format!("lines needs {key}")))
+        };
+
+        Ok(LinesSpec { skip_beg: line("skip_beg")?, index_line: line("index_line")?, skip_end: line("skip_end")? })
+    }
+}
+
+impl From<KindSpec> for FieldKind {
+    fn from(kind: KindSpec) -> FieldKind {
+        match kind {
+            KindSpec::Text => FieldKind::Text,
+            KindSpec::Integer => FieldKind::Integer,
+            KindSpec::Decimal => FieldKind::Decimal,
+            KindSpec::Percent => FieldKind::Percent,
+            KindSpec::Date => FieldKind::Date,
+            KindSpec::Time => FieldKind::Time,
+        }
+    }
+}
+
+fn into_columns(columns: Vec<ColumnSpec>) -> Result<Vec<Column>, ProfileError> {
+    if columns.is_empty() {
+        return Err(ProfileError::Invalid(String::from("a table needs at least a column")));
+    }
+
+    columns.into_iter().map(ColumnSpec::into_column).collect()
+}
+
+impl ProfileFile {
+    fn into_layout(self) -> Result<Layout, ProfileError> {
+        let base = self.base.as_deref().unwrap_or(crate::layout::IBEX35);
+        let mut layout = Layout::builtin(base).ok_or_else(|| {
+            ProfileError::Invalid(format!("unknown base layout {base}"))
+        })?;
+
+        layout.name = self.name.unwrap_or_else(|| String::from("custom"));
+
+        if let Some(x) = self.title {
+            layout.title = x;
+        }
+        if let Some(x) = self.title_prefix {
+            layout.title_prefix = x;
+        }
+        if let Some(x) = self.footer {
+            layout.footer = x;
+        }
+
+        match self.index {
+            Some(IndexSpec::Enabled(false)) => layout.index_header = None,
+            Some(IndexSpec::Enabled(true)) | None => (),
+            Some(IndexSpec::Table(table)) => {
+                if let Some(x) = table.header {
+                    layout.index_header = Some(x);
+                } else if layout.index_header.is_none() {
+                    return Err(ProfileError::Invalid(
+                        String::from("the table of the index needs a header")
+                    ));
+                }
+                if let Some(x) = table.columns {
+                    layout.index_columns = into_columns(x)?;
+                }
+            },
+        }
+
+        if let Some(table) = self.stocks {
+            if let Some(x) = table.header {
+                layout.stock_header = x;
+            }
+            if let Some(x) = table.columns {
+                layout.stock_columns = into_columns(x)?;
+            }
+        }
+
+        if let Some(x) = self.lines {
+            layout.bounds = Bounds::Fixed {
+                skip_beg: x.skip_beg,
+                index_line: x.index_line,
+                skip_end: x.skip_end,
+            };
+        }
+
+        Ok(layout)
+    }
+}
+
+// Both formats are read into a JSON value, so the profiles are checked by hand rather than
+// deriving `Deserialize`, and don't depend on the feature `serde`.
+fn toml_value(content: &str) -> Result<Value, ProfileError> {
+    let table = toml::from_str::<toml::Table>(content).map_err(ProfileError::Toml)?;
+
+    serde_json::to_value(table).map_err(ProfileError::Json)
+}
+
+fn json_value(content: &str) -> Result<Value, ProfileError> {
+    serde_json::from_str(content).map_err(ProfileError::Json)
+}
+
+/// Build a layout from the content of a TOML profile.
+pub fn from_toml(content: &str) -> Result<Layout, ProfileError> {
+    ProfileFile::from_value(&toml_value(content)?)?.into_layout()
+}
+
+/// Build a layout from the content of a JSON profile.
+pub fn from_json(content: &str) -> Result<Layout, ProfileError> {
+    ProfileFile::from_value(&json_value(content)?)?.into_layout()
+}
+
+/// Load a layout from a profile file.
+///
+/// # Description
+///
+/// The format of the file is chosen using its extension: `.toml` or `.json`. See the
+/// [module documentation][self] for a description of the content of a profile.
+///
+/// When the profile doesn't give a name to the layout, the stem of the file is used.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use ibex_parser::profile;
+/// use std::path::Path;
+///
+/// let layout = profile::load(Path::new("./tests/data/profiles/ibex35.toml")).unwrap();
+/// let parser = IbexParser::with_layout(layout);
+/// let data = parser.parse_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+/// ```
+pub fn load(path: &Path) -> Result<Layout, ProfileError> {
+    let content = read_to_string(path).map_err(ProfileError::Io)?;
+    let stem = path.file_stem().and_then(|x| x.to_str()).unwrap_or("custom");
+
+    let value = match path.extension().and_then(|x| x.to_str()) {
+        Some("toml") => toml_value(&content)?,
+        Some("json") => json_value(&content)?,
+        _ => return Err(ProfileError::UnknownFormat),
+    };
+    let profile = ProfileFile::from_value(&value)?;
+    let named = profile.name.is_some();
+    let mut layout = profile.into_layout()?;
+
+    if !named {
+        layout.name = String::from(stem);
+    }
+
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+
+    // The profiles found in the test folder describe the built-in layout of the Ibex 35.
+    #[rstest]
+    #[case("./tests/data/profiles/ibex35.toml")]
+    #[case("./tests/data/profiles/ibex35.json")]
+    fn test_profile_load(#[case] path: &str) {
+        let layout = load(Path::new(path)).unwrap();
+
+        assert_eq!(layout, Layout { name: layout.name.clone(), ..Layout::ibex35() });
+    }
+
+    #[rstest]
+    fn test_profile_default_name() {
+        let layout = load(Path::new("./tests/data/profiles/ibex35.json")).unwrap();
+
+        assert_eq!(layout.name, "ibex35");
+    }
+
+    #[rstest]
+    fn test_profile_from_toml() {
+        let layout = from_toml(r#"
+            name = "bancos"
+            base = "ibex35-sector"
+            index = false
+
+            [stocks]
+            columns = [0, "Último", { index = 5, type = "integer" }]
+
+            [lines]
+            skip_beg = 11
+            index_line = 6
+            skip_end = 5
+        "#).unwrap();
+
+        assert_eq!(layout.name, "bancos");
+        assert_eq!(layout.index_header, None);
+        assert_eq!(layout.stock_columns, vec![
+            Column::from(0),
+            Column::named("Último", FieldKind::Text),
+            Column { source: ColumnRef::Index(5), kind: FieldKind::Integer },
+        ]);
+        assert_eq!(layout.bounds, Bounds::Fixed { skip_beg: 11, index_line: 6, skip_end: 5 });
+    }
+
+    #[rstest]
+    fn test_profile_from_json() {
+        let layout = from_json(r#"{"footer": "Datos diferidos", "index": {"header": "Index"}}"#)
+            .unwrap();
+        let sector = from_json(r#"{"base": "ibex35-sector", "title": "IBEX 35 BANCOS", "title_prefix": false}"#)
+            .unwrap();
+
+        assert_eq!(layout.footer, "Datos diferidos");
+        assert!(sector.matches_title("IBEX 35 BANCOS"));
+        assert_eq!(layout.index_header, Some(String::from("Index")));
+    }
+
+    #[rstest]
+    #[case("base = \"ibex-top-dividendo\"")]
+    #[case("[stocks]\ncolumns = []")]
+    #[case("[stocks]\ncolumns = [{ name = \"Último\", index = 1 }]")]
+    #[case("[stocks]\ncolumns = [{ name = \"Último\", type = \"float\" }]")]
+    #[case("titel = \"IBEX 35\"")]
+    #[case("title_prefix = \"no\"")]
+    #[case("[stocks]\ncolumns = [-1]")]
+    #[case("[lines]\nskip_beg = 11\nindex_line = 6")]
+    #[case("base = \"mercado-continuo\"\n[index]\ncolumns = [0]")]
+    fn test_profile_wrong(#[case] content: &str) {
+        assert!(from_toml(content).is_err());
+    }
+
+    #[rstest]
+    fn test_profile_wrong_format() {
+        let result = load(Path::new("./tests/data/data_ibex.csv"));
+
+        assert!(matches!(result, Err(ProfileError::UnknownFormat)));
+    }
+}
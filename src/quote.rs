@@ -0,0 +1,393 @@
+// Copyright 2024 Felipe Torres González
+
+//! Typed quotes built from the entries returned by the parser.
+//!
+//! The parser returns each entry as a string with the values split by `;`, as they are
+//! found in the data file. This module converts those entries into typed values, so they
+//! can be stored or analysed with no need to deal with the Spanish number format.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::calendar::close_time;
+use crate::record::{Record, Records};
+use crate::timezone::localize;
+
+/// Value of the column "Hora" once the session is closed.
+const CLOSE: &str = "Cierre";
+/// Euros of a unit of the turnover found in the data files, as the column "Efectivo (miles €)"
+/// is expressed in thousands of euros.
+pub const TURNOVER_UNIT: u64 = 1000;
+
+/// The quote of an index, e.g. IBEX 35.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IndexQuote {
+    /// Name of the index, as shown in the data file.
+    pub name: String,
+    /// Date of the quote.
+    pub date: NaiveDate,
+    /// Time of the quote. `None` when the quote is the closing price of the session.
+    pub time: Option<NaiveTime>,
+    /// Last value of the index.
+    pub last: f64,
+}
+
+/// The quote of a stock.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StockQuote {
+    /// Name of the stock, as shown in the data file.
+    pub ticker: String,
+    /// Date of the quote.
+    pub date: NaiveDate,
+    /// Time of the quote. `None` when the quote is the closing price of the session.
+    pub time: Option<NaiveTime>,
+    /// Last negotiated price in euros.
+    pub last: f64,
+    /// Number of shares traded since the beginning of the session.
+    pub volume: u64,
+    /// Turnover since the beginning of the session, in euros.
+    pub turnover: f64,
+}
+
+/// The typed quotes of a data file.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Quotes {
+    /// The quote of the index. `None` for pages with no index.
+    pub index: Option<IndexQuote>,
+    /// The quotes of the stocks, in the same order as they were found in the data file.
+    pub stocks: Vec<StockQuote>,
+}
+
+/// Parse a number with the Spanish format, e.g. `10.003,00`.
+pub fn parse_decimal(value: &str) -> Option<f64> {
+    value.replace('.', "").replace(',', ".").parse().ok()
+}
+
+/// Parse a number with the Spanish format multiplied by `10^digits`, e.g. `18.341,96` with 3
+/// digits results in `18341960`.
+///
+/// The decimal mark is moved rather than multiplying the number, so no rounding error is
+/// introduced. Values with no digits, e.g. an empty value or `-`, are not numbers.
+pub fn parse_scaled(value: &str, digits: usize) -> Option<f64> {
+    let value = value.replace('.', "");
+
+    if !value.chars().any(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    let (integer, fraction) = value.split_once(',').unwrap_or((&value, ""));
+    let fraction = format!("{fraction:0<digits$}");
+    let (shifted, rest) = fraction.split_at(digits);
+
+    format!("{integer}{shifted}.{rest}0").parse().ok()
+}
+
+/// Parse an integer number with the Spanish format, e.g. `1.945.142`.
+pub fn parse_integer(value: &str) -> Option<u64> {
+    value.replace('.', "").parse().ok()
+}
+
+/// Parse a date with the format `DD/MM/YYYY`.
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%d/%m/%Y").ok()
+}
+
+/// Parse a time with the format `HH:MM:SS`.
+///
+/// The value `Cierre`, shown once the session is closed, results in `Some(None)`.
+pub fn parse_time(value: &str) -> Option<Option<NaiveTime>> {
+    if value == CLOSE {
+        Some(None)
+    } else {
+        NaiveTime::parse_from_str(value, "%H:%M:%S").ok().map(Some)
+    }
+}
+
+/// Format a number with the Spanish format, e.g. `10.003,00`, using the given decimals.
+///
+/// This is the format used by BME, so the formatted values can be mixed with the values
+/// found in the data files.
+pub fn format_decimal(value: f64, decimals: usize) -> String {
+    let formatted = format!("{:.*}", decimals, value.abs());
+    let (integer, fraction) = formatted.split_once('.').unwrap_or((&formatted, ""));
+    let mut result = String::new();
+
+    if value < 0.0 && formatted.chars().any(|x| x.is_ascii_digit() && x != '0') {
+        result.push('-');
+    }
+
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            result.push('.');
+        }
+        result.push(digit);
+    }
+
+    if !fraction.is_empty() {
+        result.push(',');
+        result.push_str(fraction);
+    }
+
+    result
+}
+
+/// Format an integer number with the Spanish format, e.g. `1.945.142`.
+pub fn format_integer(value: u64) -> String {
+    format_decimal(value as f64, 0)
+}
+
+impl IndexQuote {
+    /// Date and time of the quote, as displayed by the page. Closing prices are placed at
+    /// the end of the closing auction of their session (see [close_time]).
+    pub fn published_at(&self) -> NaiveDateTime {
+        self.date.and_time(self.time.unwrap_or_else(|| close_time(self.date)))
+    }
+
+    /// Date and time of the quote in the time zone of the market (see [localize]).
+    pub fn timestamp(&self) -> DateTime<Tz> {
+        localize(self.published_at())
+    }
+
+    /// Build a quote from an entry of the index returned by the parser.
+    ///
+    /// The entry must have the columns selected by the built-in layouts: name, date, time
+    /// and last value. `None` is returned otherwise.
+    pub fn from_entry(entry: &str) -> Option<IndexQuote> {
+        IndexQuote::from_fields(entry.split(';'))
+    }
+
+    /// Build a quote from the record of the index, with the same columns as `from_entry`.
+    pub fn from_record(record: &Record) -> Option<IndexQuote> {
+        IndexQuote::from_fields(record.fields())
+    }
+
+    fn from_fields<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<IndexQuote> {
+        let quote = IndexQuote {
+            name: String::from(fields.next()?),
+            date: parse_date(fields.next()?)?,
+            time: parse_time(fields.next()?)?,
+            last: parse_decimal(fields.next()?)?,
+        };
+
+        fields.next().is_none().then_some(quote)
+    }
+}
+
+impl StockQuote {
+    /// Date and time of the quote, as displayed by the page. Closing prices are placed at
+    /// the end of the closing auction of their session (see [close_time]).
+    pub fn published_at(&self) -> NaiveDateTime {
+        self.date.and_time(self.time.unwrap_or_else(|| close_time(self.date)))
+    }
+
+    /// Date and time of the quote in the time zone of the market (see [localize]).
+    pub fn timestamp(&self) -> DateTime<Tz> {
+        localize(self.published_at())
+    }
+
+    /// Build a quote from an entry of a stock returned by the parser.
+    ///
+    /// The entry must have the columns selected by the built-in layouts: name, date, time,
+    /// last price, volume and turnover. `None` is returned otherwise. The turnover is
+    /// converted from thousands of euros, as found in the data files, into euros.
+    ///
+    /// # Example of use
+    ///
+    /// ```rust
+    /// use ibex_parser::quote::StockQuote;
+    ///
+    /// let quote = StockQuote::from_entry("ACS;06/02/2024;17:35:05;36,5000;501.552;18.341,96")
+    ///     .unwrap();
+    /// assert_eq!(quote.volume, 501552);
+    /// assert_eq!(quote.turnover, 18341960.0);
+    /// ```
+    pub fn from_entry(entry: &str) -> Option<StockQuote> {
+        StockQuote::from_fields(entry.split(';'))
+    }
+
+    /// Build a quote from the record of a stock, with the same columns as `from_entry`.
+    pub fn from_record(record: &Record) -> Option<StockQuote> {
+        StockQuote::from_fields(record.fields())
+    }
+
+    fn from_fields<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<StockQuote> {
+        let quote = StockQuote {
+            ticker: String::from(fields.next()?),
+            date: parse_date(fields.next()?)?,
+            time: parse_time(fields.next()?)?,
+            last: parse_decimal(fields.next()?)?,
+            volume: parse_integer(fields.next()?)?,
+            turnover: parse_scaled(fields.next()?, TURNOVER_UNIT.ilog10() as usize)?,
+        };
+
+        fields.next().is_none().then_some(quote)
+    }
+}
+
+impl Quotes {
+    /// Build the quotes from the entries of a data file returned by the parser.
+    ///
+    /// When `has_index` is set, the first entry is taken as the quote of the index. `None`
+    /// is returned when any of the entries can't be converted.
+    pub fn from_entries<S: AsRef<str>>(entries: &[S], has_index: bool) -> Option<Quotes> {
+        let (index, stocks) = match entries.split_first() {
+            Some((first, rest)) if has_index => (Some(IndexQuote::from_entry(first.as_ref())?), rest),
+            _ => (None, entries),
+        };
+
+        Some(Quotes {
+            index,
+            stocks: stocks
+                .iter()
+                .map(|x| StockQuote::from_entry(x.as_ref()))
+                .collect::<Option<Vec<StockQuote>>>()?,
+        })
+    }
+
+    /// Build the quotes from the records of a data file.
+    ///
+    /// `None` is returned when any of the records can't be converted.
+    pub fn from_records(records: &Records) -> Option<Quotes> {
+        let index = match records.index() {
+            Some(x) => Some(IndexQuote::from_record(&x)?),
+            None => None,
+        };
+
+        Some(Quotes {
+            index,
+            stocks: records.stocks().map(|x| StockQuote::from_record(&x)).collect::<Option<Vec<StockQuote>>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use crate::parser_ibex::IbexParser;
+
+    #[rstest]
+    #[case("10.003,00", Some(10003.0))]
+    #[case("-0,5", Some(-0.5))]
+    #[case("114", Some(114.0))]
+    #[case("Cierre", None)]
+    fn test_parse_decimal(#[case] value: &str, #[case] expected: Option<f64>) {
+        assert_eq!(parse_decimal(value), expected);
+    }
+
+    #[rstest]
+    #[case("18.341,96", 3, Some(18341960.0))]
+    #[case("0,1", 3, Some(100.0))]
+    #[case("12,345678", 3, Some(12345.678))]
+    #[case("-0,5", 0, Some(-0.5))]
+    #[case("Cierre", 3, None)]
+    #[case("", 3, None)]
+    #[case("-", 3, None)]
+    fn test_parse_scaled(#[case] value: &str, #[case] digits: usize, #[case] expected: Option<f64>) {
+        assert_eq!(parse_scaled(value, digits), expected);
+    }
+
+    #[rstest]
+    #[case(10003.0, 2, "10.003,00")]
+    #[case(-0.5, 2, "-0,50")]
+    #[case(114.45, 4, "114,4500")]
+    #[case(-0.001, 2, "0,00")]
+    #[case(1945142.0, 0, "1.945.142")]
+    fn test_format_decimal(#[case] value: f64, #[case] decimals: usize, #[case] expected: &str) {
+        assert_eq!(format_decimal(value, decimals), expected);
+        assert_eq!(parse_decimal(expected).unwrap(), format!("{value:.decimals$}").parse::<f64>().unwrap());
+    }
+
+    #[rstest]
+    fn test_parse_time() {
+        assert_eq!(parse_time("Cierre"), Some(None));
+        assert_eq!(parse_time("17:37:03"), Some(NaiveTime::from_hms_opt(17, 37, 3)));
+        assert_eq!(parse_time("17:37"), None);
+    }
+
+    #[rstest]
+    fn test_indexquote_from_entry() {
+        let quote = IndexQuote::from_entry("IBEX 35®;06/02/2024;17:37:03;10.003,00").unwrap();
+
+        assert_eq!(quote.name, "IBEX 35®");
+        assert_eq!(quote.date, NaiveDate::from_ymd_opt(2024, 2, 6).unwrap());
+        assert_eq!(quote.last, 10003.0);
+        assert_eq!(IndexQuote::from_entry("IBEX 35®;10.003,00"), None);
+    }
+
+    #[rstest]
+    fn test_stockquote_from_entry() {
+        let quote = StockQuote::from_entry("B.SANTANDER;06/02/2024;Cierre;3,7475;49.965.519;186.520,55")
+            .unwrap();
+
+        assert_eq!(quote.ticker, "B.SANTANDER");
+        assert_eq!(quote.time, None);
+        assert_eq!(quote.volume, 49965519);
+        assert_eq!(quote.turnover, 186520550.0);
+        assert_eq!(StockQuote::from_entry("ACS;06/02/2024;Cierre;36,5000;-;-"), None);
+    }
+
+    // The session of the 24th of December ends at 14:05.
+    #[rstest]
+    #[case("B.SANTANDER;06/02/2024;Cierre;3,7475;49.965.519;186.520,55", (2024, 2, 6), (17, 35))]
+    #[case("B.SANTANDER;24/12/2024;Cierre;3,7475;49.965.519;186.520,55", (2024, 12, 24), (14, 5))]
+    #[case("B.SANTANDER;24/12/2024;13:20:00;3,7475;49.965.519;186.520,55", (2024, 12, 24), (13, 20))]
+    fn test_stockquote_published_at(#[case] entry: &str, #[case] date: (i32, u32, u32), #[case] time: (u32, u32)) {
+        let quote = StockQuote::from_entry(entry).unwrap();
+        let expected = NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap().and_hms_opt(time.0, time.1, 0);
+
+        assert_eq!(Some(quote.published_at()), expected);
+    }
+
+    #[rstest]
+    fn test_quotes_from_entries() {
+        let entries = vec![
+            "IBEX 35®;06/02/2024;17:37:03;10.003,00",
+            "ACS;06/02/2024;Cierre;36,5000;501.552;18.341,96",
+        ];
+
+        let quotes = Quotes::from_entries(&entries, true).unwrap();
+        assert!(quotes.index.is_some());
+        assert_eq!(quotes.stocks.len(), 1);
+
+        assert_eq!(Quotes::from_entries(&entries, false), None);
+
+        let content = std::fs::read_to_string("./tests/data/data_ibex.csv").unwrap();
+        let parser = IbexParser::new();
+        let records = parser.records(&content).unwrap();
+        assert_eq!(Quotes::from_records(&records), Quotes::from_entries(&records.to_entries(), true));
+        assert_eq!(Quotes::from_entries(&entries[1..], false).unwrap().stocks.len(), 1);
+    }
+
+    // The names of the fields are part of the interface: consumers read them from queues and
+    // caches, so they must not change.
+    #[cfg(feature = "serde")]
+    #[rstest]
+    fn test_quotes_serde() {
+        use serde_json::json;
+
+        let quotes = Quotes {
+            index: IndexQuote::from_entry("IBEX 35®;06/02/2024;Cierre;10.003,00"),
+            stocks: vec![StockQuote::from_entry("ACS;06/02/2024;17:35:05;36,5000;501.552;18.341,96").unwrap()],
+        };
+        let value = serde_json::to_value(&quotes).unwrap();
+
+        assert_eq!(value, json!({
+            "index": {"name": "IBEX 35®", "date": "2024-02-06", "time": null, "last": 10003.0},
+            "stocks": [{
+                "ticker": "ACS",
+                "date": "2024-02-06",
+                "time": "17:35:05",
+                "last": 36.5,
+                "volume": 501552,
+                "turnover": 18341960.0,
+            }],
+        }));
+        assert_eq!(serde_json::from_value::<Quotes>(value).unwrap(), quotes);
+    }
+}
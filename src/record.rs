@@ -0,0 +1,390 @@
+// Copyright 2024 Felipe Torres González
+
+//! Records of a data file that borrow their values from the content of the file.
+//!
+//! The entries returned by [IbexParser::parse_bytes] are new strings built for each row of
+//! the data file. That's handy, but parsing a long backfill allocates millions of strings
+//! that are dropped right after being filtered or converted. A [Record] is rather a view of
+//! a row of the data file: its values are slices of the content of the file, and they are
+//! only copied when an owned value is requested, e.g. using [Record::to_owned_record] or
+//! [StockQuote::from_record][crate::quote::StockQuote::from_record].
+//!
+//! Use [IbexParser::records] or [IbexParser::with_records] to get the records of a data file.
+//!
+//! [IbexParser::parse_bytes]: crate::parser_ibex::IbexParser::parse_bytes
+//! [IbexParser::records]: crate::parser_ibex::IbexParser::records
+//! [IbexParser::with_records]: crate::parser_ibex::IbexParser::with_records
+
+use std::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::layout::Column;
+use crate::parser_ibex::{Mode, ParseError};
+
+/// Whether a record holds the values of an index or of a stock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum RecordKind {
+    Index,
+    Stock,
+}
+
+/// The cells of a row of a data file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Cells<'a> {
+    /// A line of a text file, whose cells are separated by `\t`. The line is split when a
+    /// cell is requested, so no buffer is allocated per row.
+    Line(&'a str),
+    /// The cells of a row of an HTML table.
+    Html(&'a [String]),
+}
+
+impl<'a> Cells<'a> {
+    fn get(&self, i: usize) -> Option<&'a str> {
+        match self {
+            Cells::Line(x) => x.split('\t').nth(i),
+            Cells::Html(x) => x.get(i).map(String::as_str),
+        }
+    }
+}
+
+/// The rows of a table of a data file, and the position of the columns that are parsed.
+#[derive(Debug, Clone)]
+pub(crate) struct Table<'a> {
+    rows: Vec<Cells<'a>>,
+    columns: Vec<usize>,
+    // Errors of the rows that were skipped.
+    warnings: Vec<ParseError>,
+}
+
+impl<'a> Table<'a> {
+    /// Find the selected columns in the header of the table, and check the values of the rows.
+    ///
+    /// An error is returned when a column can't be found in the header of the table. Rows
+    /// that have no value for a column, or whose values don't match the type of their column,
+    /// are an error in [Mode::Strict], and are skipped in [Mode::Lenient].
+    pub(crate) fn new<S: AsRef<str>>(
+        header: Option<&[S]>,
+        rows: Vec<Cells<'a>>,
+        columns: &[Column],
+        mode: Mode
+    ) -> Result<Table<'a>, ParseError> {
+        let positions = columns
+            .iter()
+            .map(|x| x.resolve(header).ok_or_else(|| ParseError::ColumnNotFound(x.source.clone())))
+            .collect::<Result<Vec<usize>, ParseError>>()?;
+        let mut table = Table { rows: Vec::with_capacity(rows.len()), columns: positions, warnings: Vec::new() };
+
+        for (n, row) in rows.into_iter().enumerate() {
+            match table.check(n + 1, &row, columns) {
+                Ok(_) => table.rows.push(row),
+                Err(e) if mode == Mode::Lenient => table.warnings.push(e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Check that a row has a value of the right type for each column.
+    fn check(&self, n: usize, row: &Cells, columns: &[Column]) -> Result<(), ParseError> {
+        for (i, column) in self.columns.iter().zip(columns) {
+            let value = row.get(*i).ok_or_else(|| {
+                ParseError::MissingValue { row: n, column: column.source.clone() }
+            })?;
+
+            if !column.kind.matches(value) {
+                return Err(ParseError::WrongValue {
+                    row: n,
+                    column: column.source.clone(),
+                    value: String::from(value),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record<'b>(&'b self, kind: RecordKind, cells: &'b Cells<'a>) -> Record<'b> {
+        Record { kind, cells, columns: &self.columns }
+    }
+}
+
+/// A row of a data file, whose values are slices of the content of the file.
+///
+/// The values are the ones of the columns selected by the layout of the parser, in the same
+/// order. Formatting a record with `{}` yields the same entry returned by
+/// [IbexParser::parse_bytes][crate::parser_ibex::IbexParser::parse_bytes].
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    kind: RecordKind,
+    cells: &'a Cells<'a>,
+    columns: &'a [usize],
+}
+
+impl<'a> Record<'a> {
+    /// Whether the record holds the values of the index or of a stock.
+    pub fn kind(&self) -> RecordKind {
+        self.kind
+    }
+
+    /// Number of values of the record.
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Whether the record has no values, i.e. the layout selects no column.
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// Get the value at position `i` of the record.
+    pub fn get(&self, i: usize) -> Option<&'a str> {
+        self.cells.get(*self.columns.get(i)?)
+    }
+
+    /// Name of the stock or the index, i.e. the first value of the record.
+    pub fn name(&self) -> &'a str {
+        self.get(0).unwrap_or_default()
+    }
+
+    /// Iterate over the values of the record.
+    pub fn fields(&self) -> impl Iterator<Item = &'a str> + '_ {
+        // The values are found in the table when the records are built.
+        self.columns.iter().map(|x| self.cells.get(*x).unwrap_or_default())
+    }
+
+    /// Check whether the entry of the record (see [fmt::Display]) contains any of the strings
+    /// of `filter`. Any record matches an empty filter.
+    pub fn matches<S: AsRef<str>>(&self, filter: &[S]) -> bool {
+        filter.is_empty() || filter.iter().map(|x| x.as_ref()).any(|f| {
+            // The entry is only built for filters that span several values.
+            if f.contains(';') {
+                self.to_string().contains(f)
+            } else {
+                self.fields().any(|x| x.contains(f))
+            }
+        })
+    }
+
+    /// Copy the values of the record.
+    pub fn to_owned_record(&self) -> OwnedRecord {
+        OwnedRecord { kind: self.kind, fields: self.fields().map(String::from).collect() }
+    }
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, value) in self.fields().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            f.write_str(value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A record whose values are owned, so it can outlive the content of the data file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OwnedRecord {
+    /// Whether the record holds the values of the index or of a stock.
+    pub kind: RecordKind,
+    /// The values of the record.
+    pub fields: Vec<String>,
+}
+
+impl fmt::Display for OwnedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.fields.join(";"))
+    }
+}
+
+/// The records of a data file: the record of the index, if the page has one, and the
+/// records of the stocks.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use std::fs::read_to_string;
+///
+/// let content = read_to_string("./tests/data/data_ibex.csv").unwrap();
+/// let parser = IbexParser::new();
+/// let records = parser.records(&content).unwrap();
+///
+/// assert_eq!(records.index().unwrap().name(), "IBEX 35®");
+///
+/// // No value is copied until the matching records are formatted.
+/// let aena: Vec<String> = records.filter(&["AENA"]).map(|x| x.to_string()).collect();
+/// assert_eq!(aena, vec!["AENA;06/02/2024;Cierre;172,1000;165.458;28.298,10"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Records<'a> {
+    // Only the first row of the table of the index is used.
+    index: Option<Table<'a>>,
+    stocks: Table<'a>,
+}
+
+impl<'a> Records<'a> {
+    /// Build the records of a data file. When all the rows of the stocks were skipped, the
+    /// error of the first one is returned.
+    pub(crate) fn new(mut index: Option<Table<'a>>, stocks: Table<'a>) -> Result<Records<'a>, ParseError> {
+        // Records with no index are still valid, but the reason is reported.
+        if let Some(x) = index.as_mut().filter(|x| x.rows.is_empty()) {
+            x.warnings = x.warnings.drain(..).map(|e| ParseError::IndexSkipped(Box::new(e))).collect();
+        }
+
+        match stocks.warnings.first() {
+            Some(x) if stocks.rows.is_empty() => Err(x.clone()),
+            _ => Ok(Records { index, stocks }),
+        }
+    }
+
+    /// The record of the index. `None` for pages with no index.
+    pub fn index(&self) -> Option<Record<'_>> {
+        let table = self.index.as_ref()?;
+        table.rows.first().map(|x| table.record(RecordKind::Index, x))
+    }
+
+    /// Iterate over the records of the stocks, in the same order as in the data file.
+    pub fn stocks(&self) -> impl Iterator<Item = Record<'_>> {
+        self.stocks.rows.iter().map(|x| self.stocks.record(RecordKind::Stock, x))
+    }
+
+    /// Iterate over all the records: the one of the index first, and then the ones of the
+    /// stocks.
+    pub fn iter(&self) -> impl Iterator<Item = Record<'_>> {
+        self.index().into_iter().chain(self.stocks())
+    }
+
+    /// Iterate over the records that match `filter` (see [Record::matches]).
+    pub fn filter<'b, S: AsRef<str>>(&'b self, filter: &'b [S]) -> impl Iterator<Item = Record<'b>> {
+        self.iter().filter(|x| x.matches(filter))
+    }
+
+    /// Number of records, including the one of the index.
+    pub fn len(&self) -> usize {
+        self.stocks.rows.len() + usize::from(self.index().is_some())
+    }
+
+    /// Whether there's no record, neither of the index nor of a stock.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Errors of the rows that were skipped in [Mode::Lenient]: first the ones of the table of
+    /// the index, and then the ones of the table of the stocks.
+    pub fn warnings(&self) -> impl Iterator<Item = &ParseError> {
+        self.index.iter().flat_map(|x| x.warnings.iter()).chain(self.stocks.warnings.iter())
+    }
+
+    /// Build the entries returned by [IbexParser::parse_bytes][crate::parser_ibex::IbexParser::parse_bytes].
+    pub fn to_entries(&self) -> Vec<String> {
+        self.iter().map(|x| x.to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use crate::layout::FieldKind;
+
+    fn table<'a>(rows: &[&'a str], columns: &[Column]) -> Result<Table<'a>, ParseError> {
+        let header = ["Nombre", "Último", "Hora"];
+        Table::new(Some(&header[..]), rows.iter().map(|x| Cells::Line(x)).collect(), columns, Mode::Strict)
+    }
+
+    #[fixture]
+    fn columns() -> Vec<Column> {
+        vec![Column::named("Nombre", FieldKind::Text), Column::named("Hora", FieldKind::Time)]
+    }
+
+    #[rstest]
+    fn test_records(columns: Vec<Column>) {
+        let stocks = table(&["ACS\t36,5000\tCierre", "AENA\t171,2000\t15:54:35"], &columns).unwrap();
+        let records = Records::new(None, stocks).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert!(records.index().is_none());
+        assert_eq!(records.to_entries(), vec!["ACS;Cierre", "AENA;15:54:35"]);
+
+        let aena = records.filter(&["AEN"]).next().unwrap();
+        assert_eq!((aena.kind(), aena.name()), (RecordKind::Stock, "AENA"));
+        assert_eq!((aena.get(1), aena.get(2)), (Some("15:54:35"), None));
+        assert_eq!(
+            aena.to_owned_record(),
+            OwnedRecord { kind: RecordKind::Stock, fields: vec![String::from("AENA"), String::from("15:54:35")] }
+        );
+        assert_eq!(records.filter::<&str>(&[]).count(), 2);
+    }
+
+    // Filters are matched against the whole entry, so they may span several values.
+    #[rstest]
+    #[case(&["ACS;Cier"], &["ACS;Cierre"])]
+    #[case(&["A;15"], &["AENA;15:54:35"])]
+    #[case(&["ACS;15"], &[])]
+    #[case(&["5", "Cierre"], &["ACS;Cierre", "AENA;15:54:35"])]
+    fn test_records_filter(columns: Vec<Column>, #[case] filter: &[&str], #[case] expected: &[&str]) {
+        let stocks = table(&["ACS\t36,5000\tCierre", "AENA\t171,2000\t15:54:35"], &columns).unwrap();
+        let records = Records::new(None, stocks).unwrap();
+
+        assert_eq!(records.filter(filter).map(|x| x.to_string()).collect::<Vec<String>>(), expected);
+    }
+
+    // Rows with missing values, or values that don't match their column, are rejected.
+    #[rstest]
+    #[case(&["ACS\t36,5000"], "row 1 has no column Hora")]
+    #[case(&["ACS\t1\tCierre", "ACS\t1\t1"], "row 2 has a wrong value for the column Hora: 1")]
+    fn test_records_wrong(columns: Vec<Column>, #[case] rows: &[&str], #[case] expected: &str) {
+        assert_eq!(table(rows, &columns).unwrap_err().to_string(), expected);
+    }
+
+    // Rows with errors are skipped in lenient mode, unless there's no row left.
+    #[rstest]
+    fn test_records_lenient(columns: Vec<Column>) {
+        let rows = ["ACS\t1\t-", "AENA\t1\tCierre", "IAG\t1"];
+        let cells = |rows: &[&'static str]| rows.iter().map(|x| Cells::Line(x)).collect();
+        let header = ["Nombre", "Último", "Hora"];
+
+        let stocks = Table::new(Some(&header[..]), cells(&rows), &columns, Mode::Lenient).unwrap();
+        let records = Records::new(None, stocks).unwrap();
+        assert_eq!(records.to_entries(), vec!["AENA;Cierre"]);
+        assert_eq!(
+            records.warnings().map(|x| x.to_string()).collect::<Vec<String>>(),
+            vec!["row 1 has a wrong value for the column Hora: -", "row 3 has no column Hora"]
+        );
+
+        let stocks = Table::new(Some(&header[..]), cells(&rows[..1]), &columns, Mode::Lenient).unwrap();
+        assert_eq!(Records::new(None, stocks).unwrap_err().row(), Some(1));
+    }
+
+    #[rstest]
+    fn test_records_html(columns: Vec<Column>) {
+        let header = vec![String::from("Nombre"), String::from("Hora")];
+        let rows = [vec![String::from("ACS"), String::from("Cierre")]];
+        let cells = rows.iter().map(|x| Cells::Html(x)).collect();
+        let records = Records::new(None, Table::new(Some(&header), cells, &columns, Mode::Strict).unwrap()).unwrap();
+
+        assert_eq!(records.to_entries(), vec!["ACS;Cierre"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[rstest]
+    fn test_owned_record_serde() {
+        use serde_json::json;
+
+        let record = OwnedRecord {
+            kind: RecordKind::Stock,
+            fields: vec![String::from("ACS"), String::from("36,5000")],
+        };
+        let value = serde_json::to_value(&record).unwrap();
+
+        assert_eq!(value, json!({"kind": "stock", "fields": ["ACS", "36,5000"]}));
+        assert_eq!(serde_json::from_value::<OwnedRecord>(value).unwrap(), record);
+    }
+}
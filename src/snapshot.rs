@@ -0,0 +1,523 @@
+// Copyright 2024 Felipe Torres González
+
+//! Snapshots of the market, as captured from a page of BME.
+//!
+//! A data file is the capture of a page at some instant: the quote of the index, the quotes
+//! of its stocks, and some notes about the data, such as the delay with which it is
+//! published. A [Snapshot] bundles all of them, so there's no need to deal with the entries
+//! returned by the parser.
+
+use std::slice;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use chrono_tz::Tz;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::calendar::{Calendar, Phase};
+use crate::html::{is_html, text_lines};
+use crate::layout::Layout;
+use crate::parser_ibex::ParseError;
+use crate::quote::{IndexQuote, Quotes, StockQuote, TURNOVER_UNIT};
+use crate::timezone::{elapsed, localize};
+
+/// Code of the currency of the prices and turnover found in the data files.
+const EURO: &str = "EUR";
+/// Names of the months, as written by BME at the top of the pages.
+const MONTHS: [&str; 12] = [
+    "enero", "febrero", "marzo", "abril", "mayo", "junio",
+    "julio", "agosto", "septiembre", "octubre", "noviembre", "diciembre",
+];
+
+/// Information about the data stated in the notes found after the tables of a page.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Metadata {
+    /// Delay with which the data is published, in minutes, e.g. "Información diferida 15
+    /// minutos". `None` when not stated.
+    pub delay_minutes: Option<u32>,
+    /// Currency of the prices, e.g. "Precios expresados en euros". Euros are given by their
+    /// code (`EUR`), other currencies as written. `None` when not stated.
+    pub currency: Option<String>,
+    /// Currency of the turnover, e.g. "Efectivo expresado en miles de euros", given as
+    /// `currency`. `None` when not stated.
+    pub turnover_currency: Option<String>,
+    /// Amount of currency of a unit of the turnover, e.g. 1000 for "Efectivo expresado en miles
+    /// de euros". `None` when not stated.
+    pub turnover_unit: Option<u64>,
+    /// The notes, as found in the page.
+    pub notes: Vec<String>,
+}
+
+impl Metadata {
+    /// Extract the metadata from the notes of a page.
+    ///
+    /// Notes that state nothing known are only kept in `notes`.
+    ///
+    /// # Example of use
+    ///
+    /// ```rust
+    /// use ibex_parser::snapshot::Metadata;
+    ///
+    /// let notes = ["Información diferida 15 minutos.", "Efectivo expresado en miles de euros."];
+    /// let metadata = Metadata::from_notes(&notes);
+    ///
+    /// assert_eq!(metadata.delay_minutes, Some(15));
+    /// assert_eq!(metadata.turnover_currency.as_deref(), Some("EUR"));
+    /// assert_eq!(metadata.turnover_unit, Some(1000));
+    /// ```
+    pub fn from_notes<S: AsRef<str>>(notes: &[S]) -> Metadata {
+        let mut metadata = Metadata {
+            notes: notes.iter().map(|x| String::from(x.as_ref())).collect(),
+            ..Metadata::default()
+        };
+
+        for note in metadata.notes.iter() {
+            let note = note.trim_end_matches('.');
+
+            if let Some(x) = note.strip_prefix("Información diferida ") {
+                metadata.delay_minutes = parse_delay(x);
+            } else if let Some(x) = note.strip_prefix("Precios expresados en ") {
+                metadata.currency = Some(currency_code(x));
+            } else if let Some(x) = note.strip_prefix("Efectivo expresado en ") {
+                let (unit, currency) = match x.split_once(" de ") {
+                    Some(("miles", currency)) => (1000, currency),
+                    Some(("millones", currency)) => (1_000_000, currency),
+                    _ => (1, x),
+                };
+                metadata.turnover_unit = Some(unit);
+                metadata.turnover_currency = Some(currency_code(currency));
+            }
+        }
+
+        metadata
+    }
+
+    /// Check that the units stated by the notes are the ones assumed by the parser: prices in
+    /// euros, and turnover in thousands of euros (see [TURNOVER_UNIT]). Units that are not
+    /// stated are taken as the assumed ones.
+    pub fn check(&self) -> Result<(), ParseError> {
+        let wrong = |x: String| Err(ParseError::WrongUnits(x));
+
+        if let Some(x) = self.currency.as_deref().filter(|x| *x != EURO) {
+            return wrong(format!("prices in {x}"));
+        }
+        if let Some(x) = self.turnover_currency.as_deref().filter(|x| *x != EURO) {
+            return wrong(format!("turnover in {x}"));
+        }
+        if let Some(x) = self.turnover_unit.filter(|x| *x != TURNOVER_UNIT) {
+            return wrong(format!("turnover in units of {x}"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the code of a currency, as written in the notes of a page.
+fn currency_code(value: &str) -> String {
+    match value {
+        "euros" => String::from(EURO),
+        x => String::from(x),
+    }
+}
+
+/// Parse a delay such as `15 minutos` or `1 hora` into minutes. Delays too long to be counted
+/// in minutes are not parsed.
+fn parse_delay(value: &str) -> Option<u32> {
+    let (amount, unit) = value.split_once(' ')?;
+    let amount: u32 = amount.parse().ok()?;
+
+    match unit {
+        "minuto" | "minutos" => Some(amount),
+        "hora" | "horas" => amount.checked_mul(60),
+        _ => None,
+    }
+}
+
+/// Parse the date and time shown at the top of a page, e.g. `06 febrero 2024 17:37:03`.
+pub fn parse_page_time(value: &str) -> Option<NaiveDateTime> {
+    let mut parts = value.split(' ');
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|x| *x == month)? as u32 + 1;
+    let year: i32 = parts.next()?.parse().ok()?;
+    let time = NaiveTime::parse_from_str(parts.next()?, "%H:%M:%S").ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(NaiveDate::from_ymd_opt(year, month, day)?.and_time(time))
+}
+
+/// When a quote was published by the page, and when it was captured.
+///
+/// The page shows the time of each quote (column "Hora"), which might be far behind the
+/// instant at which the page was captured: quotes of stocks with no recent trades, or pages
+/// left open for a while. The lag between both tells how stale a quote is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Timing {
+    /// Date and time of the quote, as displayed by the page (see [StockQuote::published_at]).
+    pub published_at: NaiveDateTime,
+    /// The instant at which the page was captured. `None` when unknown.
+    pub captured_at: Option<NaiveDateTime>,
+}
+
+impl Timing {
+    /// Time elapsed from the publication of the quote to the capture of the page, taking
+    /// into account the change of the clocks between them. `None` when the capture time is
+    /// unknown.
+    pub fn lag(&self) -> Option<TimeDelta> {
+        Some(elapsed(self.published_at, self.captured_at?))
+    }
+}
+
+/// The content of a data file: the quotes of an index and its stocks at some instant.
+///
+/// # Description
+///
+/// Use [IbexParser::parse_snapshot][crate::parser_ibex::IbexParser::parse_snapshot] or
+/// [IbexParser::snapshot_file][crate::parser_ibex::IbexParser::snapshot_file] to build a
+/// snapshot from a data file.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use std::path::Path;
+///
+/// let parser = IbexParser::new();
+/// let snapshot = parser.snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+///
+/// assert_eq!(snapshot.len(), 35);
+/// assert_eq!(snapshot.get("ACS").unwrap().last, 36.5);
+/// assert_eq!(snapshot.metadata.delay_minutes, Some(15));
+///
+/// for quote in snapshot.iter().filter(|x| x.last > 100.0) {
+///     println!("{}: {}", quote.ticker, quote.last);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    /// Name of the data file, when the snapshot was parsed from a file.
+    pub source: Option<String>,
+    /// The instant of the market shown by the page, as shown at the top of the page. It's the
+    /// time of the quote of the index, so the data is already delayed at that time. `None`
+    /// when the page doesn't show it.
+    pub market_at: Option<NaiveDateTime>,
+    /// The instant at which the page was captured, e.g. the time at which the data file was
+    /// saved (see [IbexParser::snapshot_file][crate::parser_ibex::IbexParser::snapshot_file]).
+    /// `None` when unknown. Use [Snapshot::with_captured_at] to set it.
+    pub captured_at: Option<NaiveDateTime>,
+    /// The quote of the index. `None` for pages with no index.
+    pub index: Option<IndexQuote>,
+    /// The quotes of the stocks, in the same order as they were found in the data file.
+    pub stocks: Vec<StockQuote>,
+    /// Information about the data stated by the page.
+    pub metadata: Metadata,
+}
+
+impl Snapshot {
+    /// Build a snapshot from the quotes of a data file and its normalised content, from
+    /// which the time of the market and the notes are taken. The capture time is unknown. The layout is the one used to parse
+    /// the quotes.
+    ///
+    /// An error is returned when the notes state units other than the assumed ones (see
+    /// [Metadata::check]).
+    pub fn from_quotes(quotes: Quotes, content: &str, layout: &Layout) -> Result<Snapshot, ParseError> {
+        let lines: Vec<String> = if is_html(content) {
+            text_lines(content)
+        } else {
+            content.lines().map(|x| String::from(x.trim())).collect()
+        };
+        // The notes start at the footer of the layout, and end at the first empty line.
+        let notes: Vec<&str> = lines
+            .iter()
+            .skip_while(|x| !x.starts_with(&layout.footer))
+            .take_while(|x| !x.is_empty())
+            .map(|x| x.as_str())
+            .collect();
+
+        let metadata = Metadata::from_notes(&notes);
+        metadata.check()?;
+
+        Ok(Snapshot {
+            source: None,
+            market_at: lines.iter().find_map(|x| parse_page_time(x)),
+            captured_at: None,
+            index: quotes.index,
+            stocks: quotes.stocks,
+            metadata,
+        })
+    }
+
+    /// Set the instant at which the page was captured.
+    pub fn with_captured_at(mut self, captured_at: NaiveDateTime) -> Snapshot {
+        self.captured_at = Some(captured_at);
+        self
+    }
+
+    /// Delay with which the data is published, as stated by the page.
+    pub fn delay(&self) -> Option<TimeDelta> {
+        self.metadata.delay_minutes.map(|x| TimeDelta::minutes(x.into()))
+    }
+
+    /// The instant at which the page was captured, in the time zone of the market.
+    pub fn captured_timestamp(&self) -> Option<DateTime<Tz>> {
+        self.captured_at.map(localize)
+    }
+
+    /// The instant of the market shown by the snapshot, as local time of Madrid: the time
+    /// shown by the page. When the page doesn't show it, the capture time minus the delay
+    /// with which the data is published (none when the page doesn't state it).
+    pub fn market_time(&self) -> Option<NaiveDateTime> {
+        self.market_at.or_else(|| {
+            let market_time = self.captured_timestamp()? - self.delay().unwrap_or_default();
+            Some(market_time.naive_local())
+        })
+    }
+
+    /// The instant at which the page was captured, as local time of Madrid. When it's
+    /// unknown, it's estimated as the time shown by the page plus the delay with which the
+    /// data is published.
+    pub fn capture_time(&self) -> Option<NaiveDateTime> {
+        self.captured_at.or_else(|| {
+            let capture_time = localize(self.market_at?) + self.delay().unwrap_or_default();
+            Some(capture_time.naive_local())
+        })
+    }
+
+    /// Phase of the market shown by the snapshot (see [Snapshot::market_time]). Snapshots
+    /// out of a session, i.e. whose phase is not open, show stale data. `None` when the
+    /// time of the market is unknown.
+    pub fn phase(&self, calendar: &Calendar) -> Option<Phase> {
+        self.market_time().map(|x| calendar.phase(x))
+    }
+
+    /// When a quote was published, and when the snapshot was captured.
+    pub fn timing(&self, published_at: NaiveDateTime) -> Timing {
+        Timing { published_at, captured_at: self.captured_at }
+    }
+
+    /// Iterate over the quotes of the stocks along with their timing.
+    pub fn timings(&self) -> impl Iterator<Item = (&StockQuote, Timing)> {
+        self.stocks.iter().map(|x| (x, self.timing(x.published_at())))
+    }
+
+    /// How stale the snapshot was when captured: the lag of its most recent quote, including
+    /// the quote of the index. `None` when the capture time is unknown.
+    pub fn lag(&self) -> Option<TimeDelta> {
+        let published = self.index.iter().map(|x| x.published_at())
+            .chain(self.stocks.iter().map(|x| x.published_at()))
+            .max()?;
+
+        self.timing(published).lag()
+    }
+
+    /// Get the quote of a stock using its name, as shown in the data file.
+    pub fn get(&self, ticker: &str) -> Option<&StockQuote> {
+        self.stocks.iter().find(|x| x.ticker == ticker)
+    }
+
+    /// Iterate over the quotes of the stocks.
+    pub fn iter(&self) -> slice::Iter<'_, StockQuote> {
+        self.stocks.iter()
+    }
+
+    /// Names of the stocks, in the same order as they were found in the data file.
+    pub fn tickers(&self) -> impl Iterator<Item = &str> {
+        self.stocks.iter().map(|x| x.ticker.as_str())
+    }
+
+    /// Number of stocks in the snapshot.
+    pub fn len(&self) -> usize {
+        self.stocks.len()
+    }
+
+    /// Whether the snapshot has no stocks.
+    pub fn is_empty(&self) -> bool {
+        self.stocks.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a Snapshot {
+    type Item = &'a StockQuote;
+    type IntoIter = slice::Iter<'a, StockQuote>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl From<Snapshot> for Quotes {
+    fn from(snapshot: Snapshot) -> Quotes {
+        Quotes { index: snapshot.index, stocks: snapshot.stocks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use std::fs::read_to_string;
+    use std::path::Path;
+    use crate::parser_ibex::IbexParser;
+
+    #[rstest]
+    #[case("06 febrero 2024 17:37:03", NaiveDate::from_ymd_opt(2024, 2, 6).unwrap().and_hms_opt(17, 37, 3))]
+    #[case("31 diciembre 2023 09:00:00", NaiveDate::from_ymd_opt(2023, 12, 31).unwrap().and_hms_opt(9, 0, 0))]
+    #[case("06 February 2024 17:37:03", None)]
+    #[case("GRÁFICO IBEX 35", None)]
+    fn test_parse_page_time(#[case] value: &str, #[case] expected: Option<NaiveDateTime>) {
+        assert_eq!(parse_page_time(value), expected);
+    }
+
+    #[rstest]
+    #[case("15 minutos", Some(15))]
+    #[case("1 hora", Some(60))]
+    #[case("unos minutos", None)]
+    #[case("4294967295 horas", None)]
+    fn test_parse_delay(#[case] value: &str, #[case] expected: Option<u32>) {
+        assert_eq!(parse_delay(value), expected);
+    }
+
+    #[rstest]
+    #[case("./tests/data/data_ibex.csv")]
+    #[case("./tests/data/data_ibex_win.csv")]
+    #[case("./tests/data/data_ibex.html")]
+    fn test_snapshot(#[case] path: &str) {
+        let snapshot = IbexParser::new().snapshot_file(Path::new(path)).unwrap();
+
+        assert_eq!(snapshot.source.as_deref(), Path::new(path).file_name().unwrap().to_str());
+        assert_eq!(snapshot.market_at, NaiveDate::from_ymd_opt(2024, 2, 6).unwrap().and_hms_opt(17, 37, 3));
+        assert!(snapshot.captured_at.is_some());
+        assert_eq!(snapshot.index.as_ref().unwrap().last, 10003.0);
+        assert_eq!(snapshot.len(), 35);
+        assert_eq!(snapshot.tickers().next(), Some("ACCIONA"));
+        assert_eq!(snapshot.get("UNICAJA").unwrap().volume, 17621854);
+        assert_eq!(snapshot.get("IBEX 35®"), None);
+        assert_eq!((&snapshot).into_iter().count(), 35);
+        assert_eq!(snapshot.metadata.delay_minutes, Some(15));
+        assert_eq!(snapshot.metadata.currency.as_deref(), Some("EUR"));
+        assert_eq!(snapshot.metadata.turnover_unit, Some(1000));
+        assert_eq!(snapshot.get("UNICAJA").unwrap().turnover, 16331860.0);
+        assert_eq!(snapshot.metadata.notes[..3], [
+            "Información diferida 15 minutos.",
+            "Precios expresados en euros.",
+            "Efectivo expresado en miles de euros.",
+        ]);
+    }
+
+    #[rstest]
+    fn test_snapshot_timing() {
+        let at = |h, m, s| NaiveDate::from_ymd_opt(2024, 2, 6).unwrap().and_hms_opt(h, m, s).unwrap();
+        // The page shows the market at 17:37:03, with the data delayed 15 minutes.
+        let snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap()
+            .with_captured_at(at(17, 52, 3));
+
+        // The closing price of ACS is published at the close of the session.
+        let (quote, timing) = snapshot.timings().find(|(x, _)| x.ticker == "ACS").unwrap();
+        assert_eq!(quote.published_at(), at(17, 35, 0));
+        assert_eq!(timing, Timing { published_at: at(17, 35, 0), captured_at: Some(at(17, 52, 3)) });
+        assert_eq!(timing.lag(), Some(TimeDelta::seconds(1023)));
+        assert_eq!(Timing { captured_at: None, ..timing }.lag(), None);
+
+        // The quote of the index is the most recent one, so the lag is the delay of the data.
+        assert_eq!(snapshot.lag(), Some(TimeDelta::minutes(15)));
+        assert_eq!(snapshot.market_time(), Some(at(17, 37, 3)));
+        assert_eq!(snapshot.capture_time(), Some(at(17, 52, 3)));
+
+        // Each time is estimated from the other one when it's unknown.
+        let snapshot = Snapshot { market_at: None, ..snapshot };
+        assert_eq!(snapshot.market_time(), Some(at(17, 37, 3)));
+        let snapshot = Snapshot { market_at: Some(at(17, 37, 3)), captured_at: None, ..snapshot };
+        assert_eq!(snapshot.capture_time(), Some(at(17, 52, 3)));
+        assert_eq!(snapshot.lag(), None);
+    }
+
+    fn february(day: u32, h: u32, m: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 2, day).unwrap().and_hms_opt(h, m, 0).unwrap()
+    }
+
+    // The page shows the closing prices at 17:37, after the closing auction.
+    #[rstest]
+    #[case(None, Phase::AfterClose)]
+    #[case(Some(february(6, 17, 22)), Phase::ContinuousTrading)]
+    #[case(Some(february(6, 17, 33)), Phase::ClosingAuction)]
+    #[case(Some(february(7, 8, 45)), Phase::OpeningAuction)]
+    #[case(Some(february(10, 12, 0)), Phase::NonTradingDay)]
+    fn test_snapshot_phase(#[case] market_at: Option<NaiveDateTime>, #[case] expected: Phase) {
+        let mut snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+        snapshot.market_at = market_at.or(snapshot.market_at);
+
+        assert_eq!(snapshot.phase(&Calendar::new()), Some(expected));
+    }
+
+    // The clocks go forward an hour on 2024-03-31 at 02:00.
+    #[rstest]
+    fn test_snapshot_timing_dst() {
+        let at = |h, m| NaiveDate::from_ymd_opt(2024, 3, 31).unwrap().and_hms_opt(h, m, 0).unwrap();
+        let mut snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap()
+            .with_captured_at(at(3, 5));
+        snapshot.market_at = None;
+        let timing = snapshot.timing(at(1, 55));
+
+        assert_eq!(timing.lag(), Some(TimeDelta::minutes(10)));
+        assert_eq!(snapshot.market_time(), Some(at(1, 50)));
+        assert_eq!(Snapshot { market_at: Some(at(1, 50)), captured_at: None, ..snapshot.clone() }.capture_time(), Some(at(3, 5)));
+        assert_eq!(snapshot.captured_timestamp().unwrap().to_rfc3339(), "2024-03-31T03:05:00+02:00");
+    }
+
+    #[rstest]
+    #[case("Efectivo expresado en miles de euros.", Ok(()))]
+    #[case("Efectivo expresado en euros.", Err(String::from("turnover in units of 1")))]
+    #[case("Efectivo expresado en miles de dólares.", Err(String::from("turnover in dólares")))]
+    #[case("Precios expresados en dólares.", Err(String::from("prices in dólares")))]
+    #[case("Los precios no incluyen comisiones.", Ok(()))]
+    fn test_metadata_check(#[case] note: &str, #[case] expected: Result<(), String>) {
+        let metadata = Metadata::from_notes(&["Información diferida 15 minutos.", note]);
+
+        assert_eq!(metadata.check(), expected.map_err(ParseError::WrongUnits));
+    }
+
+    // The turnover would be wrong by a factor of 1000 if the units of the page changed.
+    #[rstest]
+    fn test_snapshot_wrong_units() {
+        let content = read_to_string("./tests/data/data_ibex.csv").unwrap()
+            .replace("Efectivo expresado en miles de euros.", "Efectivo expresado en euros.");
+        let parser = IbexParser::new();
+
+        assert_eq!(
+            parser.try_snapshot(&content).unwrap_err().to_string(),
+            "the page states other units than the expected ones: turnover in units of 1"
+        );
+        assert_eq!(parser.parse_quotes(content.as_bytes()), None);
+        assert!(parser.parse_str(&content).is_some());
+    }
+
+    #[rstest]
+    fn test_snapshot_no_index() {
+        let parser = IbexParser::with_layout(Layout::mercado_continuo());
+        let snapshot = parser.snapshot_file(Path::new("./tests/data/layouts/data_mercado_continuo.csv")).unwrap();
+
+        assert_eq!(snapshot.index, None);
+        assert_eq!(snapshot.metadata.delay_minutes, Some(15));
+        assert_eq!(Quotes::from(snapshot.clone()).stocks, snapshot.stocks);
+    }
+
+    #[cfg(feature = "serde")]
+    #[rstest]
+    fn test_snapshot_serde() {
+        let snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+        let value = serde_json::to_value(&snapshot).unwrap();
+
+        assert_eq!(value["source"], "data_ibex.csv");
+        assert_eq!(value["market_at"], "2024-02-06T17:37:03");
+        assert_eq!(value["index"]["name"], "IBEX 35®");
+        assert_eq!(value["stocks"][0]["ticker"], "ACCIONA");
+        assert_eq!(value["metadata"]["delay_minutes"], 15);
+        assert_eq!(value["metadata"]["currency"], "EUR");
+        assert_eq!(serde_json::from_value::<Snapshot>(value).unwrap(), snapshot);
+    }
+}
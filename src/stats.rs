@@ -0,0 +1,362 @@
+// Copyright 2024 Felipe Torres González
+
+//! Summary statistics of the quotes of each stock.
+//!
+//! A quick look at a set of data files: how many quotes were found for each stock, which
+//! period they cover, and how the price moved along that period.
+//!
+//! The breadth of the market can be followed along a session too: how many stocks advance or
+//! decline in each snapshot, which ones move the most, and which ones concentrate the
+//! turnover (see [breadth]). The contribution of each stock to the index isn't computed: it
+//! needs the weights of the stocks in the index, and the pages of BME don't show them.
+
+use std::collections::{BTreeMap, HashMap};
+use chrono::{NaiveDate, NaiveDateTime};
+use crate::quote::StockQuote;
+use crate::snapshot::Snapshot;
+
+/// Summary of the quotes of a stock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TickerStats {
+    /// Name of the stock, as shown in the data files.
+    pub ticker: String,
+    /// Number of quotes.
+    pub quotes: usize,
+    /// Number of sessions with quotes.
+    pub sessions: usize,
+    /// Date of the first quote.
+    pub first: NaiveDate,
+    /// Date of the last quote.
+    pub last: NaiveDate,
+    /// Price of the first quote.
+    pub open: f64,
+    /// Highest price.
+    pub high: f64,
+    /// Lowest price.
+    pub low: f64,
+    /// Price of the last quote.
+    pub close: f64,
+    /// Change of the price between the first and the last quote, in percent.
+    ///
+    /// It's not defined when the price of the first quote is zero.
+    pub change: Option<f64>,
+}
+
+/// Compute the summary of the quotes of each stock.
+///
+/// Quotes can be given in any order: they are sorted by their date and time, and closing
+/// prices are placed at the end of their session.
+///
+/// ## Return
+///
+/// The summaries sorted by the name of the stock.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use ibex_parser::stats::summarize;
+/// use std::fs::read;
+///
+/// let quotes = IbexParser::new().parse_quotes(&read("./tests/data/data_ibex.csv").unwrap()).unwrap();
+/// let stats = summarize(&quotes.stocks);
+///
+/// assert_eq!(stats[0].ticker, "ACCIONA");
+/// assert_eq!(stats[0].quotes, 1);
+/// ```
+pub fn summarize(quotes: &[StockQuote]) -> Vec<TickerStats> {
+    let mut tickers: BTreeMap<&str, Vec<&StockQuote>> = BTreeMap::new();
+
+    for quote in quotes {
+        tickers.entry(&quote.ticker).or_default().push(quote);
+    }
+
+    tickers
+        .into_iter()
+        .map(|(ticker, mut quotes)| {
+            quotes.sort_by_key(|x| x.published_at());
+
+            let (first, last) = (quotes[0], quotes[quotes.len() - 1]);
+            let mut sessions: Vec<NaiveDate> = quotes.iter().map(|x| x.date).collect();
+            sessions.dedup();
+
+            TickerStats {
+                ticker: String::from(ticker),
+                quotes: quotes.len(),
+                sessions: sessions.len(),
+                first: first.date,
+                last: last.date,
+                open: first.last,
+                high: quotes.iter().map(|x| x.last).fold(f64::MIN, f64::max),
+                low: quotes.iter().map(|x| x.last).fold(f64::MAX, f64::min),
+                close: last.last,
+                change: (first.last != 0.0).then(|| (last.last - first.last) / first.last * 100.0),
+            }
+        })
+        .collect()
+}
+
+/// A stock among the largest changes of a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mover {
+    /// Name of the stock, as shown in the data files.
+    pub ticker: String,
+    /// Last price.
+    pub last: f64,
+    /// Change of the price, in percent (see [Breadth]).
+    pub change: f64,
+}
+
+/// Breadth of the market in a snapshot.
+///
+/// The change of a stock is given relative to the close of the previous session, when the
+/// snapshots include it, or else relative to the open of the session. BME's pages don't show
+/// the open of the stocks, so the open is the first price of the session found in the
+/// snapshots. Stocks whose reference price is zero or not a number have no change, so they
+/// are counted apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breadth {
+    /// Source of the snapshot.
+    pub source: Option<String>,
+    /// Capture time of the snapshot.
+    pub captured_at: Option<NaiveDateTime>,
+    /// Last value of the index, when the snapshot includes it.
+    pub index: Option<f64>,
+    /// Number of stocks whose price went up.
+    pub advancers: usize,
+    /// Number of stocks whose price went down.
+    pub decliners: usize,
+    /// Number of stocks whose price didn't change.
+    pub unchanged: usize,
+    /// Number of stocks whose change can't be computed, because their reference price is zero
+    /// or not a number.
+    pub unpriced: usize,
+    /// Stocks whose price is above the open of the session, in percent of the stocks of the
+    /// snapshot.
+    pub above_open: f64,
+    /// Stocks with the largest share in the turnover of the snapshot, in percent, from the
+    /// largest share.
+    pub turnover_share: Vec<(String, f64)>,
+    /// Stocks with the largest rises, from the largest one.
+    pub gainers: Vec<Mover>,
+    /// Stocks with the largest falls, from the largest one.
+    pub losers: Vec<Mover>,
+}
+
+/// Compute the breadth of the market in each snapshot.
+///
+/// # Description
+///
+/// Snapshots can be given in any order: the open of each stock is the price of its quote with
+/// the earliest time in the session, and the close of the previous session is its quote of
+/// the latest previous date, both searched among all the snapshots. Only the `top` stocks with
+/// the largest rises and falls are kept as gainers and losers, and only the `top` stocks with
+/// the largest share of the turnover are kept.
+///
+/// ## Return
+///
+/// The breadth of each snapshot, in the same order as the snapshots.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use ibex_parser::stats::breadth;
+/// use std::path::Path;
+///
+/// let snapshot = IbexParser::new().snapshot_file(Path::new("./tests/data/data_ibex.csv")).unwrap();
+/// let breadth = breadth(&[snapshot], 3);
+///
+/// assert_eq!(breadth[0].unchanged, 35);
+/// assert_eq!(breadth[0].turnover_share[0].0, "REPSOL");
+/// ```
+pub fn breadth(snapshots: &[Snapshot], top: usize) -> Vec<Breadth> {
+    // Quote with the earliest and the latest time of each stock and session.
+    let mut sessions: HashMap<(&str, NaiveDate), (&StockQuote, &StockQuote)> = HashMap::new();
+
+    for quote in snapshots.iter().flat_map(|x| x.iter()) {
+        let key = |x: &StockQuote| x.published_at();
+
+        sessions
+            .entry((&quote.ticker, quote.date))
+            .and_modify(|(first, last)| {
+                if key(quote) < key(first) {
+                    *first = quote;
+                }
+                if key(quote) >= key(last) {
+                    *last = quote;
+                }
+            })
+            .or_insert((quote, quote));
+    }
+
+    // Close of the previous session of each stock and session.
+    let mut dates: BTreeMap<&str, Vec<NaiveDate>> = BTreeMap::new();
+    for (ticker, date) in sessions.keys() {
+        dates.entry(ticker).or_default().push(*date);
+    }
+    let previous_close = |quote: &StockQuote| {
+        let date = dates[quote.ticker.as_str()].iter().filter(|x| **x < quote.date).max()?;
+        Some(sessions[&(quote.ticker.as_str(), *date)].1.last)
+    };
+
+    snapshots
+        .iter()
+        .map(|snapshot| {
+            let mut changes: Vec<Mover> = Vec::new();
+            let mut above_open = 0;
+            let mut unpriced = 0;
+
+            for quote in snapshot.iter() {
+                let open = sessions[&(quote.ticker.as_str(), quote.date)].0.last;
+                let reference = previous_close(quote).unwrap_or(open);
+
+                if quote.last > open {
+                    above_open += 1;
+                }
+                if reference == 0.0 || reference.is_nan() {
+                    unpriced += 1;
+                    continue;
+                }
+                changes.push(Mover {
+                    ticker: quote.ticker.clone(),
+                    last: quote.last,
+                    change: (quote.last - reference) / reference * 100.0,
+                });
+            }
+
+            let total: f64 = snapshot.iter().map(|x| x.turnover).sum();
+            let mut turnover_share: Vec<(String, f64)> = snapshot
+                .iter()
+                .map(|x| (x.ticker.clone(), if total > 0.0 { x.turnover / total * 100.0 } else { 0.0 }))
+                .collect();
+            turnover_share.sort_by(|a, b| b.1.total_cmp(&a.1));
+            turnover_share.truncate(top);
+
+            changes.sort_by(|a, b| b.change.total_cmp(&a.change));
+            let gainers = changes.iter().filter(|x| x.change > 0.0).take(top).cloned().collect();
+            let losers = changes.iter().rev().filter(|x| x.change < 0.0).take(top).cloned().collect();
+
+            Breadth {
+                source: snapshot.source.clone(),
+                captured_at: snapshot.captured_at,
+                index: snapshot.index.as_ref().map(|x| x.last),
+                advancers: changes.iter().filter(|x| x.change > 0.0).count(),
+                decliners: changes.iter().filter(|x| x.change < 0.0).count(),
+                unchanged: changes.iter().filter(|x| x.change == 0.0).count(),
+                unpriced,
+                above_open: if snapshot.is_empty() { 0.0 } else { above_open as f64 / snapshot.len() as f64 * 100.0 },
+                turnover_share,
+                gainers,
+                losers,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use chrono::NaiveTime;
+
+    fn quote(ticker: &str, day: u32, time: Option<u32>, last: f64) -> StockQuote {
+        StockQuote {
+            ticker: String::from(ticker),
+            date: NaiveDate::from_ymd_opt(2024, 2, day).unwrap(),
+            time: time.map(|h| NaiveTime::from_hms_opt(h, 0, 0).unwrap()),
+            last,
+            volume: 0,
+            turnover: 0.0,
+        }
+    }
+
+    #[rstest]
+    fn test_summarize() {
+        let quotes = vec![
+            quote("ACS", 7, Some(10), 38.0),
+            quote("ACS", 6, None, 40.0),
+            quote("ACS", 6, Some(10), 36.0),
+            quote("ACS", 7, Some(12), 35.0),
+            quote("ACCIONA", 6, None, 114.45),
+        ];
+
+        let stats = summarize(&quotes);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].ticker, "ACCIONA");
+
+        let acs = &stats[1];
+        assert_eq!((acs.quotes, acs.sessions), (4, 2));
+        assert_eq!(acs.first, NaiveDate::from_ymd_opt(2024, 2, 6).unwrap());
+        assert_eq!((acs.open, acs.high, acs.low, acs.close), (36.0, 40.0, 35.0, 35.0));
+        assert!((acs.change.unwrap() + 2.7778).abs() < 1e-4);
+    }
+
+    #[rstest]
+    fn test_summarize_zero_price() {
+        let stats = summarize(&[quote("ACS", 6, Some(10), 0.0), quote("ACS", 6, Some(12), 36.0)]);
+
+        assert_eq!((stats[0].open, stats[0].close), (0.0, 36.0));
+        assert_eq!(stats[0].change, None);
+    }
+
+    fn snapshot(quotes: Vec<StockQuote>) -> Snapshot {
+        Snapshot {
+            source: None,
+            market_at: None,
+            captured_at: None,
+            index: None,
+            stocks: quotes,
+            metadata: Default::default(),
+        }
+    }
+
+    #[rstest]
+    fn test_breadth() {
+        let stocks = |day, time, prices: [f64; 3]| -> Vec<StockQuote> {
+            ["ACS", "AENA", "BBVA"]
+                .into_iter()
+                .zip(prices)
+                .map(|(ticker, last)| StockQuote { turnover: last * 10.0, ..quote(ticker, day, time, last) })
+                .collect()
+        };
+        let snapshots = vec![
+            snapshot(stocks(7, Some(12), [38.0, 170.0, 9.0])),
+            snapshot(stocks(7, Some(10), [36.0, 170.0, 10.0])),
+            snapshot(stocks(6, None, [36.0, 172.0, 9.0])),
+        ];
+
+        let breadth = breadth(&snapshots, 1);
+        assert_eq!(breadth.len(), 3);
+
+        // Relative to the close of the previous session: ACS rises, AENA falls.
+        let noon = &breadth[0];
+        assert_eq!((noon.advancers, noon.decliners, noon.unchanged), (1, 1, 1));
+        assert!((noon.above_open - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(noon.gainers, vec![Mover { ticker: String::from("ACS"), last: 38.0, change: 2.0 / 36.0 * 100.0 }]);
+        assert_eq!(noon.losers[0].ticker, "AENA");
+        assert_eq!(noon.turnover_share, vec![(String::from("AENA"), 1700.0 / 2170.0 * 100.0)]);
+        assert_eq!((noon.unpriced, noon.index), (0, None));
+
+        assert_eq!((breadth[1].advancers, breadth[1].decliners, breadth[1].above_open), (1, 1, 0.0));
+
+        // With no previous session, the open is the reference.
+        let first = &breadth[2];
+        assert_eq!((first.unchanged, first.gainers.len(), first.losers.len()), (3, 0, 0));
+    }
+
+    #[rstest]
+    #[case(0.0)]
+    #[case(f64::NAN)]
+    fn test_breadth_unpriced(#[case] price: f64) {
+        let snapshots = vec![
+            snapshot(vec![quote("ACS", 6, None, price), quote("AENA", 6, None, 170.0)]),
+            snapshot(vec![quote("ACS", 7, Some(10), 36.0), quote("AENA", 7, Some(10), 172.0)]),
+        ];
+
+        let breadth = &breadth(&snapshots, 3)[1];
+        assert_eq!((breadth.advancers, breadth.decliners, breadth.unchanged, breadth.unpriced), (1, 0, 0, 1));
+        assert_eq!(breadth.gainers[0].ticker, "AENA");
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright 2024 Felipe Torres González
+
+//! Storage of the parsed quotes in a SQLite database.
+//!
+//! This module is only available when the feature `sqlite` is enabled. It keeps the typed
+//! quotes of many data files in a single local database, so they can be queried later
+//! with no need to parse the data files again.
+//!
+//! # Schema
+//!
+//! - `tickers`: names of the indices and stocks, and their kind (`index` or `stock`).
+//! - `index_quotes`: quotes of the indices.
+//! - `stock_quotes`: quotes of the stocks.
+//!
+//! Quotes are keyed by their ticker and their timestamp (date and time), so storing the
+//! same quote twice updates the stored values rather than duplicating them. Dates are
+//! stored as `YYYY-MM-DD`, and times as `HH:MM:SS` or `close` for the closing price.
+//! Prices and turnover are stored in euros.
+//!
+//! The version of the schema is kept in `PRAGMA user_version`. Databases written by a previous
+//! version are migrated when they are opened, and databases written by a later version are
+//! not opened.
+
+use std::path::Path;
+use chrono::NaiveTime;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use crate::quote::{Quotes, StockQuote};
+
+/// Value stored in the column `time` for the closing price of a session.
+const CLOSE_TIME: &str = "close";
+/// Version of the schema:
+///
+/// - 0: the turnover is stored in thousands of euros.
+/// - 1: the turnover is stored in euros.
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS tickers (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    kind TEXT NOT NULL CHECK (kind IN ('index', 'stock'))
+);
+CREATE TABLE IF NOT EXISTS index_quotes (
+    ticker_id INTEGER NOT NULL REFERENCES tickers(id),
+    date TEXT NOT NULL,
+    time TEXT NOT NULL,
+    last REAL NOT NULL,
+    PRIMARY KEY (ticker_id, date, time)
+);
+CREATE TABLE IF NOT EXISTS stock_quotes (
+    ticker_id INTEGER NOT NULL REFERENCES tickers(id),
+    date TEXT NOT NULL,
+    time TEXT NOT NULL,
+    last REAL NOT NULL,
+    volume INTEGER NOT NULL,
+    turnover REAL NOT NULL,
+    PRIMARY KEY (ticker_id, date, time)
+);
+"#;
+
+/// A SQLite database that stores quotes.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::parser_ibex::IbexParser;
+/// use ibex_parser::storage::Storage;
+/// use std::fs::read;
+///
+/// let parser = IbexParser::new();
+/// let quotes = parser.parse_quotes(&read("./tests/data/data_ibex.csv").unwrap()).unwrap();
+///
+/// let mut storage = Storage::open_in_memory().unwrap();
+/// storage.store(&quotes).unwrap();
+/// assert_eq!(storage.stock_quotes("ACS").unwrap().len(), 1);
+/// ```
+pub struct Storage {
+    conn: Connection,
+}
+
+fn time_to_sql(time: Option<NaiveTime>) -> String {
+    match time {
+        Some(x) => x.format("%H:%M:%S").to_string(),
+        None => String::from(CLOSE_TIME),
+    }
+}
+
+/// Read a time stored by [time_to_sql]. A value that is not a time is an error of the column
+/// `index` of the query, rather than a closing price.
+fn time_from_sql(index: usize, value: &str) -> Result<Option<NaiveTime>> {
+    if value == CLOSE_TIME {
+        return Ok(None);
+    }
+
+    NaiveTime::parse_from_str(value, "%H:%M:%S").map(Some).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+impl Storage {
+    /// Open a database stored in a file, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Storage> {
+        Storage::init(Connection::open(path)?)
+    }
+
+    /// Open a database that lives in memory, mostly useful for testing.
+    pub fn open_in_memory() -> Result<Storage> {
+        Storage::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Storage> {
+        let tx = conn.transaction()?;
+        let version: i64 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        // New databases have no tables yet, and need no migration.
+        let existing: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'stock_quotes')",
+            [],
+            |row| row.get(0),
+        )?;
+
+        if version > SCHEMA_VERSION {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+                Some(format!("the version of the schema ({version}) is newer than the supported one ({SCHEMA_VERSION})")),
+            ));
+        }
+        if existing && version < 1 {
+            tx.execute("UPDATE stock_quotes SET turnover = turnover * 1000", [])?;
+        }
+
+        tx.execute_batch(SCHEMA)?;
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        tx.commit()?;
+
+        Ok(Storage { conn })
+    }
+
+    /// Store the quotes of a data file.
+    ///
+    /// # Description
+    ///
+    /// All the quotes are stored in a single transaction: either all of them are stored, or
+    /// none is. Quotes that were already stored, i.e. with the same ticker, date and time,
+    /// are updated with the given values.
+    ///
+    /// ## Return
+    ///
+    /// The number of quotes that were stored or updated.
+    pub fn store(&mut self, quotes: &Quotes) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut count = 0;
+
+        if let Some(index) = &quotes.index {
+            let id = Storage::ticker_id(&tx, &index.name, "index")?;
+            count += tx.execute(
+                "INSERT INTO index_quotes (ticker_id, date, time, last) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (ticker_id, date, time) DO UPDATE SET last = excluded.last",
+                params![id, index.date.to_string(), time_to_sql(index.time), index.last],
+            )?;
+        }
+
+        for stock in quotes.stocks.iter() {
+            let id = Storage::ticker_id(&tx, &stock.ticker, "stock")?;
+            count += tx.execute(
+                "INSERT INTO stock_quotes (ticker_id, date, time, last, volume, turnover)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (ticker_id, date, time) DO UPDATE SET
+                 last = excluded.last, volume = excluded.volume, turnover = excluded.turnover",
+                params![
+                    id, stock.date.to_string(), time_to_sql(stock.time),
+                    stock.last, stock.volume, stock.turnover
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(count)
+    }
+
+    /// Get the id of a ticker, inserting it when it is not stored yet.
+    fn ticker_id(conn: &Connection, name: &str, kind: &str) -> Result<i64> {
+        let id = conn
+            .query_row("SELECT id FROM tickers WHERE name = ?1", [name], |row| row.get(0))
+            .optional()?;
+
+        match id {
+            Some(x) => Ok(x),
+            None => {
+                conn.execute("INSERT INTO tickers (name, kind) VALUES (?1, ?2)", [name, kind])?;
+                Ok(conn.last_insert_rowid())
+            },
+        }
+    }
+
+    /// Names of the stored tickers of a kind (`index` or `stock`), in alphabetical order.
+    pub fn tickers(&self, kind: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM tickers WHERE kind = ?1 ORDER BY name")?;
+        let names = stmt.query_map([kind], |row| row.get(0))?;
+
+        names.collect()
+    }
+
+    /// Get the stored quotes of a stock, ordered by their timestamp.
+    pub fn stock_quotes(&self, ticker: &str) -> Result<Vec<StockQuote>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT q.date, q.time, q.last, q.volume, q.turnover
+             FROM stock_quotes q JOIN tickers t ON t.id = q.ticker_id
+             WHERE t.name = ?1 ORDER BY q.date, q.time",
+        )?;
+        let quotes = stmt.query_map([ticker], |row| {
+            let date: String = row.get(0)?;
+            let time: String = row.get(1)?;
+
+            Ok(StockQuote {
+                ticker: String::from(ticker),
+                date: date.parse().map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+                })?,
+                time: time_from_sql(1, &time)?,
+                last: row.get(2)?,
+                volume: row.get(3)?,
+                turnover: row.get(4)?,
+            })
+        })?;
+
+        quotes.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use std::fs::read;
+    use crate::parser_ibex::IbexParser;
+
+    #[fixture]
+    fn quotes() -> Quotes {
+        IbexParser::new().parse_quotes(&read("./tests/data/data_ibex.csv").unwrap()).unwrap()
+    }
+
+    #[rstest]
+    fn test_storage_store(quotes: Quotes) {
+        let mut storage = Storage::open_in_memory().unwrap();
+
+        assert_eq!(storage.store(&quotes).unwrap(), 36);
+        assert_eq!(storage.tickers("index").unwrap(), vec!["IBEX 35®"]);
+        assert_eq!(storage.tickers("stock").unwrap().len(), 35);
+
+        let acs = storage.stock_quotes("ACS").unwrap();
+        assert_eq!(acs.len(), 1);
+        assert_eq!(acs[0], quotes.stocks[3]);
+    }
+
+    // Storing the same quotes twice updates them rather than duplicating them.
+    #[rstest]
+    fn test_storage_upsert(mut quotes: Quotes) {
+        let mut storage = Storage::open_in_memory().unwrap();
+
+        storage.store(&quotes).unwrap();
+        quotes.stocks[3].last = 37.0;
+        storage.store(&quotes).unwrap();
+
+        let acs = storage.stock_quotes("ACS").unwrap();
+        assert_eq!(acs.len(), 1);
+        assert_eq!(acs[0].last, 37.0);
+    }
+
+    #[rstest]
+    fn test_storage_timestamps(mut quotes: Quotes) {
+        let mut storage = Storage::open_in_memory().unwrap();
+
+        storage.store(&quotes).unwrap();
+        quotes.stocks[3].time = NaiveTime::from_hms_opt(15, 19, 51);
+        storage.store(&quotes).unwrap();
+
+        let acs = storage.stock_quotes("ACS").unwrap();
+        assert_eq!(acs.len(), 2);
+        assert_eq!(acs[0].time, NaiveTime::from_hms_opt(15, 19, 51));
+        assert_eq!(acs[1].time, None);
+    }
+
+    // Databases written before the version of the schema was kept stored the turnover in
+    // thousands of euros.
+    #[rstest]
+    fn test_storage_migration(quotes: Quotes) {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute("INSERT INTO tickers (id, name, kind) VALUES (1, 'ACS', 'stock')", []).unwrap();
+        conn.execute(
+            "INSERT INTO stock_quotes VALUES (1, '2024-02-06', 'close', 36.5, 501552, 18341.96)",
+            [],
+        ).unwrap();
+
+        let storage = Storage::init(conn).unwrap();
+        let acs = storage.stock_quotes("ACS").unwrap();
+        assert!((acs[0].turnover - quotes.stocks[3].turnover).abs() < 1e-6);
+
+        // The migration is only done once.
+        let storage = Storage::init(storage.conn).unwrap();
+        assert_eq!(storage.stock_quotes("ACS").unwrap(), acs);
+    }
+
+    #[rstest]
+    fn test_storage_newer_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+
+        let error = Storage::init(conn).err().unwrap();
+        assert!(error.to_string().contains("newer than the supported one"));
+    }
+
+    // A stored time that can't be read is an error, rather than a closing price.
+    #[rstest]
+    fn test_storage_wrong_time(quotes: Quotes) {
+        let mut storage = Storage::open_in_memory().unwrap();
+
+        storage.store(&quotes).unwrap();
+        storage.conn.execute("UPDATE stock_quotes SET time = '25:61'", []).unwrap();
+
+        assert!(matches!(
+            storage.stock_quotes("ACS"),
+            Err(rusqlite::Error::FromSqlConversionFailure(1, _, _))
+        ));
+    }
+}
@@ -0,0 +1,30 @@
+// Copyright 2024 Felipe Torres González
+
+//! Helpers shared by the unit tests of several modules.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rstest::fixture;
+
+/// A directory that is removed at the end of each test.
+pub(crate) struct TempDir(pub(crate) PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Build an empty directory whose name is unique among the tests that run at the same time.
+#[fixture]
+pub(crate) fn tmp() -> TempDir {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("ibex_parser_{}_{nanos}_{count}", std::process::id()));
+
+    fs::create_dir_all(&path).unwrap();
+    TempDir(path)
+}
@@ -0,0 +1,137 @@
+// Copyright 2024 Felipe Torres González
+
+//! Time zone of the dates and times found in the data files.
+//!
+//! BME shows dates and times in the local time of Madrid, with no zone: CET (UTC+1) in
+//! winter, and CEST (UTC+2) in summer. The parser keeps them as found, so this module places
+//! them in the zone `Europe/Madrid`, which tells the offset of each instant, including the
+//! days on which the clocks change.
+
+use std::fmt;
+use std::time::SystemTime;
+use chrono::{DateTime, LocalResult, NaiveDateTime, SecondsFormat, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Time zone of the market.
+pub const MARKET_TZ: Tz = chrono_tz::Europe::Madrid;
+
+/// Place a date and time shown by BME in the time zone of the market.
+///
+/// The clocks of Madrid skip an hour in spring (02:00 to 03:00) and repeat an hour in
+/// autumn (02:00 to 03:00 again). A time that doesn't exist in spring is moved forward an
+/// hour, as a clock that wasn't changed would show it. A repeated time in autumn is taken as
+/// its first occurrence, still in summer time. No market session is open at those hours.
+///
+/// # Example of use
+///
+/// ```rust
+/// use ibex_parser::timezone::localize;
+/// use chrono::NaiveDate;
+///
+/// let winter = NaiveDate::from_ymd_opt(2024, 2, 6).unwrap().and_hms_opt(17, 35, 0).unwrap();
+/// let summer = NaiveDate::from_ymd_opt(2024, 7, 5).unwrap().and_hms_opt(17, 35, 0).unwrap();
+///
+/// assert_eq!(localize(winter).to_rfc3339(), "2024-02-06T17:35:00+01:00");
+/// assert_eq!(localize(summer).to_rfc3339(), "2024-07-05T17:35:00+02:00");
+/// ```
+pub fn localize(local: NaiveDateTime) -> DateTime<Tz> {
+    match MARKET_TZ.from_local_datetime(&local) {
+        LocalResult::Single(x) => x,
+        LocalResult::Ambiguous(x, _) => x,
+        LocalResult::None => localize(local + TimeDelta::hours(1)),
+    }
+}
+
+/// Time elapsed between two dates and times shown by BME, taking into account the change of
+/// the clocks between them.
+pub fn elapsed(from: NaiveDateTime, to: NaiveDateTime) -> TimeDelta {
+    localize(to) - localize(from)
+}
+
+/// Local time of Madrid of an instant of the system, e.g. the time at which a file was
+/// modified.
+pub fn local_time(instant: SystemTime) -> NaiveDateTime {
+    DateTime::<Utc>::from(instant).with_timezone(&MARKET_TZ).naive_local()
+}
+
+/// How dates and times are written by the export of the quotes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TimeFormat {
+    /// Local time of Madrid with no zone, as shown by BME, e.g. `2024-02-06T17:35:00`.
+    #[default]
+    Local,
+    /// UTC, e.g. `2024-02-06T16:35:00Z`.
+    Utc,
+    /// RFC 3339, local time of Madrid with its offset, e.g. `2024-02-06T17:35:00+01:00`.
+    Rfc3339,
+}
+
+impl fmt::Display for TimeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeFormat::Local => write!(f, "local"),
+            TimeFormat::Utc => write!(f, "utc"),
+            TimeFormat::Rfc3339 => write!(f, "rfc3339"),
+        }
+    }
+}
+
+impl TimeFormat {
+    /// Write a date and time shown by BME with this format.
+    pub fn format(&self, local: NaiveDateTime) -> String {
+        match self {
+            TimeFormat::Local => local.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            TimeFormat::Utc => localize(local).with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Secs, true),
+            TimeFormat::Rfc3339 => localize(local).to_rfc3339_opts(SecondsFormat::Secs, false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use chrono::NaiveDate;
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, min, 0).unwrap()
+    }
+
+    // The clocks change on 2024-03-31 at 02:00 (to 03:00), and on 2024-10-27 at 03:00 (to
+    // 02:00).
+    #[rstest]
+    #[case(at(2024, 2, 6, 17, 35), "2024-02-06T16:35:00Z", "2024-02-06T17:35:00+01:00")]
+    #[case(at(2024, 3, 31, 1, 59), "2024-03-31T00:59:00Z", "2024-03-31T01:59:00+01:00")]
+    #[case(at(2024, 3, 31, 2, 30), "2024-03-31T01:30:00Z", "2024-03-31T03:30:00+02:00")]
+    #[case(at(2024, 3, 31, 3, 0), "2024-03-31T01:00:00Z", "2024-03-31T03:00:00+02:00")]
+    #[case(at(2024, 10, 27, 2, 30), "2024-10-27T00:30:00Z", "2024-10-27T02:30:00+02:00")]
+    #[case(at(2024, 10, 27, 3, 0), "2024-10-27T02:00:00Z", "2024-10-27T03:00:00+01:00")]
+    #[case(at(2024, 10, 28, 9, 0), "2024-10-28T08:00:00Z", "2024-10-28T09:00:00+01:00")]
+    fn test_time_format(#[case] local: NaiveDateTime, #[case] utc: &str, #[case] rfc3339: &str) {
+        assert_eq!(TimeFormat::Utc.format(local), utc);
+        assert_eq!(TimeFormat::Rfc3339.format(local), rfc3339);
+        assert_eq!(TimeFormat::Local.format(local), local.format("%Y-%m-%dT%H:%M:%S").to_string());
+    }
+
+    #[rstest]
+    #[case(at(2024, 3, 31, 1, 50), at(2024, 3, 31, 3, 10), 20)]
+    #[case(at(2024, 10, 27, 1, 50), at(2024, 10, 27, 3, 10), 140)]
+    #[case(at(2024, 10, 27, 17, 35), at(2024, 10, 27, 17, 37), 2)]
+    fn test_elapsed(#[case] from: NaiveDateTime, #[case] to: NaiveDateTime, #[case] minutes: i64) {
+        assert_eq!(elapsed(from, to), TimeDelta::minutes(minutes));
+    }
+
+    #[rstest]
+    #[case(at(2024, 2, 6, 17, 35))]
+    #[case(at(2024, 7, 5, 17, 35))]
+    #[case(at(2024, 10, 27, 2, 30))]
+    fn test_local_time(#[case] local: NaiveDateTime) {
+        assert_eq!(local_time(SystemTime::from(localize(local))), local);
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2024 Felipe Torres González
+
+//! Watching a directory for new data files.
+//!
+//! Data files are usually saved by a browser automation every few minutes during the session.
+//! This module watches the directory where they are saved, and reports each new data file
+//! once it is fully written, so it isn't parsed while it is still half empty.
+//!
+//! A file is considered fully written when:
+//! - The program that wrote it closes it (only reported on Linux, by inotify).
+//! - It is moved into the directory, as browsers do once a download is finished.
+//! - Its size doesn't change for a while (see [DirWatcher::with_settle]).
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Time with no changes after which a file is considered fully written.
+pub const DEFAULT_SETTLE: Duration = Duration::from_secs(2);
+
+/// Watcher of the data files that are written into a directory.
+///
+/// # Example of use
+///
+/// ```rust,no_run
+/// use ibex_parser::is_data_file;
+/// use ibex_parser::watch::DirWatcher;
+/// use std::path::Path;
+///
+/// let mut watcher = DirWatcher::new(Path::new("./"), |x| is_data_file(x, None, None)).unwrap();
+///
+/// while let Some(file) = watcher.next_file(None) {
+///     println!("New data file: {}", file.display());
+/// }
+/// ```
+pub struct DirWatcher {
+    // Events stop when the watcher is dropped.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    accept: Box<dyn Fn(&Path) -> bool + Send>,
+    settle: Duration,
+    // Files that are being written: size and time of the last change.
+    pending: HashMap<PathBuf, (u64, Instant)>,
+    ready: VecDeque<PathBuf>,
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    fs::metadata(path).ok().filter(|x| x.is_file()).map(|x| x.len())
+}
+
+impl DirWatcher {
+    /// Start watching a directory (not recursively).
+    ///
+    /// Only the files whose path satisfies `accept` are reported, e.g. using
+    /// [is_data_file][crate::is_data_file].
+    pub fn new(dir: &Path, accept: impl Fn(&Path) -> bool + Send + 'static) -> notify::Result<DirWatcher> {
+        let (tx, events) = channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        Ok(DirWatcher {
+            _watcher: watcher,
+            events,
+            accept: Box::new(accept),
+            settle: DEFAULT_SETTLE,
+            pending: HashMap::new(),
+            ready: VecDeque::new(),
+        })
+    }
+
+    /// Set the time with no changes after which a file is considered fully written.
+    pub fn with_settle(mut self, settle: Duration) -> DirWatcher {
+        self.settle = settle;
+        self
+    }
+
+    /// Wait for the next data file that is fully written.
+    ///
+    /// # Description
+    ///
+    /// Files are reported in the order they were completed. A file that is written again
+    /// later is reported again.
+    ///
+    /// ## Arguments
+    ///
+    /// - `timeout` the maximum time to wait for a file. `None` waits forever.
+    ///
+    /// ## Return
+    ///
+    /// The path of the file, or `None` when the timeout expires or the directory can't be
+    /// watched anymore.
+    pub fn next_file(&mut self, timeout: Option<Duration>) -> Option<PathBuf> {
+        let deadline = timeout.map(|x| Instant::now() + x);
+
+        loop {
+            self.promote_settled();
+
+            if let Some(x) = self.ready.pop_front() {
+                return Some(x);
+            }
+
+            // Wake up when the next pending file might be settled, or at the deadline.
+            let settled = self.pending.values().map(|(_, t)| *t + self.settle).min();
+            let wake = match (settled, deadline) {
+                (Some(x), Some(y)) => Some(x.min(y)),
+                (x, y) => x.or(y),
+            };
+
+            let event = match wake {
+                Some(x) => self.events.recv_timeout(x.saturating_duration_since(Instant::now())),
+                None => self.events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match event {
+                Ok(Ok(x)) => self.handle(x),
+                // Errors of the watcher don't stop the watch.
+                Ok(Err(_)) => (),
+                Err(RecvTimeoutError::Timeout) => {
+                    if deadline.is_some_and(|x| Instant::now() >= x) {
+                        self.promote_settled();
+                        return self.ready.pop_front();
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event.kind {
+            // The file was closed after writing, or moved into the directory.
+            EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Modify(ModifyKind::Name(_)) => {
+                // Renames within the directory give the old path first.
+                for path in event.paths.iter() {
+                    self.pending.remove(path);
+                }
+                if let Some(path) = event.paths.last() {
+                    if (self.accept)(path) && file_size(path).is_some() {
+                        self.push_ready(path.clone());
+                    }
+                }
+            },
+            EventKind::Create(_) | EventKind::Modify(_) => {
+                for path in event.paths.into_iter().filter(|x| (self.accept)(x)) {
+                    if let Some(size) = file_size(&path) {
+                        self.pending.insert(path, (size, Instant::now()));
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn push_ready(&mut self, path: PathBuf) {
+        if !self.ready.contains(&path) {
+            self.ready.push_back(path);
+        }
+    }
+
+    // Move the files whose size didn't change for a while to the ready queue.
+    fn promote_settled(&mut self) {
+        let now = Instant::now();
+        let due: Vec<PathBuf> = self.pending
+            .iter()
+            .filter(|(_, (_, t))| now.duration_since(*t) >= self.settle)
+            .map(|(x, _)| x.clone())
+            .collect();
+
+        for path in due {
+            let (size, _) = self.pending.remove(&path).unwrap();
+
+            match file_size(&path) {
+                Some(x) if x == size => self.push_ready(path),
+                // The file is still growing.
+                Some(x) => {
+                    self.pending.insert(path, (x, now));
+                },
+                // The file was removed.
+                None => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use pretty_assertions::assert_eq;
+    use crate::testing::*;
+    use crate::is_data_file;
+
+    #[fixture]
+    fn watcher(tmp: TempDir) -> (TempDir, DirWatcher) {
+        let watcher = DirWatcher::new(&tmp.0, |x| is_data_file(x, None, None))
+            .unwrap()
+            .with_settle(Duration::from_millis(200));
+
+        (tmp, watcher)
+    }
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(5));
+
+    #[rstest]
+    fn test_watch_new_file(watcher: (TempDir, DirWatcher)) {
+        let (tmp, mut watcher) = watcher;
+        let path = tmp.0.join("data_ibex(1).csv");
+
+        fs::write(tmp.0.join("notes.txt"), "IBEX 35").unwrap();
+        fs::write(&path, "IBEX 35").unwrap();
+
+        assert_eq!(watcher.next_file(TIMEOUT), Some(path));
+        assert_eq!(watcher.next_file(Some(Duration::from_millis(500))), None);
+    }
+
+    // Browsers save the downloads with a temporary name, and rename them once finished.
+    #[rstest]
+    fn test_watch_renamed_file(watcher: (TempDir, DirWatcher)) {
+        let (tmp, mut watcher) = watcher;
+        let part = tmp.0.join("data_ibex(2).csv.part");
+        let path = tmp.0.join("data_ibex(2).csv");
+
+        fs::write(&part, "IBEX 35").unwrap();
+        fs::rename(&part, &path).unwrap();
+
+        assert_eq!(watcher.next_file(TIMEOUT), Some(path));
+    }
+
+    #[rstest]
+    fn test_watch_timeout(watcher: (TempDir, DirWatcher)) {
+        let (_tmp, mut watcher) = watcher;
+
+        assert_eq!(watcher.next_file(Some(Duration::from_millis(100))), None);
+    }
+}